@@ -19,7 +19,7 @@ fn run() -> Result<()> {
     // Create a vector of urls we would like to download.
     // These urls represent the annual reports for Apple, Inc. from 2010 to 2017.
     let base = "https://www.sec.gov/Archives/edgar/data/";
-    let urls = vec![
+    let urls = [
         "320193/000119312510238044/d10k.htm",
         "320193/000119312511282113/d220209d10k.htm",
         "320193/000119312512444068/d411355d10k.htm",
@@ -28,16 +28,18 @@ fn run() -> Result<()> {
         "320193/000119312515356351/d17062d10k.htm",
         "320193/000162828016020309/a201610-k9242016.htm",
         "320193/000032019317000070/a10-k20179302017.htm",
-    ].iter()
-        .map(|stem| format!("{}{}", &base, stem))
-        .collect::<Vec<String>>();
+    ]
+    .iter()
+    .map(|stem| format!("{}{}", &base, stem))
+    .collect::<Vec<String>>();
 
     // Turn the vector of urls into a vector of boxed Document trait objects (here we'll
     // be using the SimpleDocument struct as one possible implementer of the Document trait).
     // For this batch, we set the wkhtmltopdf option to false; so when we feed this list
     // to the Client it will just download the raw webpages in html format instead of
     // first converting them to PDF.
-    let html_documents = urls.iter()
+    let html_documents = urls
+        .iter()
         .enumerate()
         .map(|(i, url_string)| {
             let filename = format!("Apple 10-K {}.html", i + 2010);
@@ -53,7 +55,8 @@ fn run() -> Result<()> {
     // (to show off additional functionality).  This time we'll set the wkhtmltopdf
     // option to true; so when we feed this list to the Client it will first convert
     // the wepages to PDF before writing them to disk.
-    let pdf_documents = urls.iter()
+    let pdf_documents = urls
+        .iter()
         .enumerate()
         .map(|(i, url_string)| {
             let filename = format!("Apple 10-K {}.pdf", i + 2010);
@@ -86,8 +89,17 @@ fn run() -> Result<()> {
     // Let the client go. It will download and write to disk all the
     // documents while simultaneously respecting the 'requests per second' and
     // other limits we provided. If you already have the documents on disk,
-    // the client will not redownload them.
-    client.get_documents(&mut documents)?;
+    // the client will not redownload them. Instead of stopping at the first
+    // failure, the client returns a report describing what happened to
+    // every document.
+    let report = client.get_documents(&mut documents)?;
+    for document in report.failed() {
+        println!(
+            "failed to download {}: {:?}",
+            document.url(),
+            document.error()
+        );
+    }
 
     // Note: Here, if you want to, you can now access the raw bytes of all the urls
     // you downloaded, since they are now stored on each SimpleDocument in addition
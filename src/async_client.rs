@@ -0,0 +1,892 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Either, Loop};
+use futures::{stream, Async, Future, Poll, Stream};
+use reqwest::header::{ContentLength, Headers};
+use reqwest::unstable::async as reqwest_async;
+use reqwest::StatusCode;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_process::CommandExt;
+use url::Url;
+
+use client::{
+    combine, converts_from_download, freshness, hand_over, read_bytes, share, targets, Target,
+};
+use converter::{self, Converter};
+use digest::{Digest, HashingWriter};
+use document::Document;
+use error::{
+    duplicate_error, ConversionError, DigestMismatch, Error, RejectedError, Result, TimeoutError,
+    TimeoutKind,
+};
+use limits::Limits;
+use observer::Observer;
+use output_format::OutputFormat;
+use part_file::{PartFile, Resume};
+use refresh::{Freshness, RefreshPolicy};
+use report::{DocumentReport, Outcome};
+use request::Request;
+use response_info::ResponseInfo;
+use retry::RetryPolicy;
+use semaphore::{CpuPermit, HostPermit, Semaphore};
+use temp_file::{TempFile, TempFiles};
+use timeouts::Timeouts;
+use validators::Validators;
+use wkhtmltopdf;
+
+type BoxFuture<T> = Box<dyn Future<Item = T, Error = Error>>;
+
+/// An `AsyncClient` is the asynchronous counterpart of `Client`. It runs on a
+/// `tokio_core` reactor and, instead of returning a `BatchReport` once every
+/// document is finished, returns a `DocumentStream` that yields each document
+/// along with its `DocumentReport` as soon as that document is finished. It
+/// honors the same requests per second, per host, thread, retry and timeout
+/// settings as `Client`; `max_threads_io` bounds the number of documents in
+/// flight. Unlike `Client::get_documents`, documents that share a request are
+/// fetched once each, since the stream never sees the documents it hasn't
+/// pulled yet; the `Document::outputs` of a single document still share one
+/// fetch.
+/// To cancel a run, drop its `DocumentStream`: downloads in flight keep what
+/// they have in `<path>.part`, and conversions in flight are killed and their
+/// temp files removed.
+///
+/// Create one with `ClientBuilder::build_async`.
+#[derive(Clone, Debug)]
+pub struct AsyncClient {
+    pub(crate) handle: Handle,
+    pub(crate) inner: reqwest_async::Client,
+    pub(crate) keep_bytes: bool,
+    pub(crate) limits: Limits,
+    pub(crate) max_threads_io: usize,
+    pub(crate) observer: Observer,
+    pub(crate) refresh_policy: RefreshPolicy,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) semaphore: Arc<Semaphore>,
+    pub(crate) temp_files: Arc<TempFiles>,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) verify_existing_digests: bool,
+    pub(crate) wkhtmltopdf_settings: wkhtmltopdf::Settings,
+}
+
+/// A `DocumentStream` is the `Stream` returned by `AsyncClient::get_documents`.
+/// It yields every document along with its `DocumentReport` in the order the
+/// documents finish. Failed documents are reported through their
+/// `DocumentReport`; the stream itself only errors if the input stream does.
+pub struct DocumentStream<D> {
+    inner: Box<dyn Stream<Item = (D, DocumentReport), Error = Error>>,
+}
+
+impl<D> fmt::Debug for DocumentStream<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DocumentStream").finish()
+    }
+}
+
+impl<D> Stream for DocumentStream<D> {
+    type Item = (D, DocumentReport);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Error> {
+        self.inner.poll()
+    }
+}
+
+/// What a successful attempt produced
+struct Fetched {
+    outcome: Outcome,
+    response: ResponseInfo,
+    bytes_written: Option<u64>,
+}
+
+impl AsyncClient {
+    /// Downloads the documents of `documents` and writes them to disk. If a
+    /// document already exists on disk it will not be redownloaded, unless the
+    /// client's `RefreshPolicy` says it has to be revalidated. Documents
+    /// are pulled from `documents` as capacity frees up, so the input may be
+    /// unbounded.
+    pub fn get_documents<S, D>(&self, documents: S) -> DocumentStream<D>
+    where
+        S: Stream<Item = D, Error = Error> + 'static,
+        D: Document + 'static,
+    {
+        let client = self.clone();
+        let stream = documents
+            .map(move |document| client.get_document(document))
+            .buffer_unordered(self.max_threads_io);
+        DocumentStream {
+            inner: Box::new(stream),
+        }
+    }
+
+    /// Same as `get_documents`, but takes anything that can be turned into an
+    /// iterator of documents (e.g. a `Vec`).
+    pub fn get_documents_iter<I, D>(&self, documents: I) -> DocumentStream<D>
+    where
+        I: IntoIterator<Item = D>,
+        I::IntoIter: 'static,
+        D: Document + 'static,
+    {
+        self.get_documents(stream::iter_ok(documents))
+    }
+
+    fn get_document<D>(&self, mut document: D) -> BoxFuture<(D, DocumentReport)>
+    where
+        D: Document + 'static,
+    {
+        let start = Instant::now();
+        let url = document.url().clone();
+        let timeouts = document.timeouts().or(self.timeouts);
+        self.observer.on_queued(&url, document.path());
+        let mut reports = Vec::new();
+        let mut raw = Vec::new();
+        let mut to_convert = Vec::new();
+        for target in targets(&document) {
+            let freshness = freshness(
+                self.refresh_policy,
+                self.verify_existing_digests,
+                &document,
+                &target,
+            );
+            let validators = match freshness {
+                Freshness::Missing => None,
+                Freshness::Stale(validators) => validators,
+                Freshness::Fresh => {
+                    let report = self.skip_existing(&mut document, &target);
+                    reports.push((target.index, report));
+                    continue;
+                },
+            };
+            match Converter::new(target.format) {
+                Some(converter) => to_convert.push((target, converter)),
+                None => raw.push((target, validators)),
+            }
+        }
+        if raw.is_empty() && to_convert.is_empty() {
+            let report = self.finish(reports, start);
+            return Box::new(future::ok((document, report)));
+        }
+
+        let client = self.clone();
+        let request = Request::new(&document);
+        let settings = document
+            .wkhtmltopdf_settings()
+            .or(&self.wkhtmltopdf_settings);
+        let expected_digest = document.expected_digest();
+        let from_download = converts_from_download(&self.limits, &document);
+        let future = AcquireHost::new(&self.semaphore, url.clone()).and_then(move |permit| {
+            for (target, _) in &raw {
+                client.observer.on_started(&url, &target.path);
+            }
+            for (target, _) in &to_convert {
+                client.observer.on_started(&url, &target.path);
+            }
+            // A conditional request only makes sense if every output has the
+            // same version on disk.
+            let same_validators = raw.windows(2).all(|raw| raw[0].1 == raw[1].1);
+            let mut raw = raw.into_iter();
+            // The first raw output is downloaded and copied to the others.
+            // Without one, several outputs to be converted (or one that has to
+            // be checked first) are converted from a download to a temp file
+            // instead of each fetching the URL. The cache is `None` for a raw
+            // output, and holds the temp file (if it could be created)
+            // otherwise.
+            let download = match raw.next() {
+                Some((target, validators)) => {
+                    let validators = if same_validators { validators } else { None };
+                    Some(Ok((target, validators, None)))
+                },
+                None if to_convert.len() > 1 || from_download => {
+                    let path = &to_convert[0].0.path;
+                    let download = match client.temp_files.create(path) {
+                        Ok(temp_file) => {
+                            let cache = Target {
+                                index: 0,
+                                path: PathBuf::from(temp_file.path()),
+                                observed_path: path.clone(),
+                                format: OutputFormat::Raw,
+                                first: false,
+                            };
+                            Ok((cache, None, Some(Some(temp_file))))
+                        },
+                        Err(e) => {
+                            let cache = Target {
+                                index: 0,
+                                path: path.clone(),
+                                observed_path: path.clone(),
+                                format: OutputFormat::Raw,
+                                first: false,
+                            };
+                            Err((cache, e))
+                        },
+                    };
+                    Some(download)
+                },
+                None => None,
+            };
+            let raw = raw.map(|(target, _)| target).collect::<Vec<_>>();
+            let downloaded = match download {
+                Some(Err((target, e))) => {
+                    Either::B(future::ok(Some((target, Err(e), 0, Some(None)))))
+                },
+                Some(Ok((target, validators, cache))) => {
+                    let fetch = {
+                        let target = target.clone();
+                        let request = request.clone();
+                        move |client: &AsyncClient| {
+                            client.get_url(
+                                target.clone(),
+                                request.clone(),
+                                validators.clone(),
+                                expected_digest.clone(),
+                                timeouts,
+                            )
+                        }
+                    };
+                    let observed_path = target.observed_path.clone();
+                    let future = client.retry(url.clone(), observed_path, true, fetch);
+                    Either::A(
+                        future.map(|(result, attempts)| Some((target, result, attempts, cache))),
+                    )
+                },
+                None => Either::B(future::ok(None)),
+            };
+            downloaded.and_then(move |downloaded| {
+                let (source, cache) = match downloaded {
+                    Some((target, result, attempts, cache)) => {
+                        let report = client.report(&mut document, &target, result, attempts);
+                        for target in raw {
+                            let report = share(
+                                &report,
+                                &mut document,
+                                &target,
+                                &client.temp_files,
+                                client.refresh_policy,
+                                client.keep_bytes,
+                            );
+                            reports.push((target.index, report));
+                        }
+                        let source = match report.error() {
+                            Some(error) => Err(duplicate_error(error)),
+                            None => Ok((PathBuf::from(report.path()), report.response.clone())),
+                        };
+                        let attempts = report.attempts();
+                        if cache.is_none() {
+                            reports.push((target.index, report));
+                        }
+                        (Some((source, attempts)), cache)
+                    },
+                    None => (None, None),
+                };
+                let (fold_client, fold_url) = (client.clone(), url.clone());
+                let conversions = stream::iter_ok::<_, Error>(to_convert).fold(
+                    (document, reports),
+                    move |(mut document, mut reports), (target, converter)| {
+                        let source = match source {
+                            Some((Err(ref error), attempts)) => {
+                                let error = duplicate_error(error);
+                                let mut report =
+                                    DocumentReport::failed(fold_url.clone(), target.path, error);
+                                report.attempts = attempts;
+                                reports.push((target.index, report));
+                                return Either::A(future::ok((document, reports)));
+                            },
+                            Some((Ok(ref source), _)) => Some(source.clone()),
+                            None => None,
+                        };
+                        // Converting from a download doesn't send a request.
+                        let requests = source.is_none();
+                        let fetch = {
+                            let path = target.path.clone();
+                            let request = request.clone();
+                            let settings = settings.clone();
+                            move |client: &AsyncClient| {
+                                client.get_converted(
+                                    path.clone(),
+                                    converter,
+                                    request.clone(),
+                                    settings.clone(),
+                                    timeouts,
+                                    source.clone(),
+                                )
+                            }
+                        };
+                        let client = fold_client.clone();
+                        let future = fold_client.retry(
+                            fold_url.clone(),
+                            target.path.clone(),
+                            requests,
+                            fetch,
+                        );
+                        Either::B(future.map(move |(result, attempts)| {
+                            let report = client.report(&mut document, &target, result, attempts);
+                            reports.push((target.index, report));
+                            (document, reports)
+                        }))
+                    },
+                );
+                conversions.map(move |(document, reports)| {
+                    drop(permit);
+                    if let Some(Some(cache)) = cache {
+                        // The temp file itself is removed when it is dropped.
+                        PartFile::new(cache.path()).discard();
+                        if let Err(e) = client.refresh_policy.remove(cache.path()) {
+                            warn!("{}", e);
+                        }
+                    }
+                    let report = client.finish(reports, start);
+                    (document, report)
+                })
+            })
+        });
+        Box::new(future)
+    }
+
+    /// Returns the report of an output that exists on disk and is fresh,
+    /// handing the document its bytes.
+    fn skip_existing<D: Document>(&self, document: &mut D, target: &Target) -> DocumentReport {
+        let url = document.url().clone();
+        let path = target.path.clone();
+        match read_bytes(self.keep_bytes && target.first, &path) {
+            Ok(bytes) => {
+                trace!("processed {:?}", &url);
+                self.observer.on_skipped_existing(&url, &path);
+                let response = self.refresh_policy.stored_response(&url, &path);
+                hand_over(document, target, bytes, response.as_ref());
+                let mut report = DocumentReport::new(url, path, Outcome::SkippedExisting);
+                report.response = response;
+                report
+            },
+            Err(e) => DocumentReport::failed(url, path, e),
+        }
+    }
+
+    /// Returns the report of `target`, an output of `document`, from the
+    /// result of fetching (or converting) it, handing the document its bytes
+    /// and `ResponseInfo`.
+    fn report<D: Document>(
+        &self,
+        document: &mut D,
+        target: &Target,
+        result: Result<Fetched>,
+        attempts: usize,
+    ) -> DocumentReport {
+        let url = document.url().clone();
+        let path = target.path.clone();
+        let result = result.and_then(|fetched| {
+            let bytes = read_bytes(self.keep_bytes && target.first, &path)?;
+            Ok((fetched, bytes))
+        });
+        let mut report = match result {
+            Ok((fetched, bytes)) => {
+                info!("downloaded {:?}", &url);
+                let mut report = DocumentReport::new(url, path, fetched.outcome);
+                report.status = fetched.response.status();
+                report.bytes_written = fetched.bytes_written;
+                hand_over(document, target, bytes, Some(&fetched.response));
+                report.response = Some(fetched.response);
+                report
+            },
+            Err(e) => DocumentReport::failed(url, path, e),
+        };
+        report.attempts = attempts;
+        report
+    }
+
+    /// Returns the report of a document from the reports of its outputs and
+    /// lets the observer know that the document is finished.
+    fn finish(&self, reports: Vec<(usize, DocumentReport)>, start: Instant) -> DocumentReport {
+        let elapsed = start.elapsed();
+        let reports = reports
+            .into_iter()
+            .map(|(index, mut report)| {
+                report.elapsed = elapsed;
+                (index, report)
+            })
+            .collect();
+        let report = combine(reports);
+        self.observer.on_finished(&report);
+        report
+    }
+
+    /// Asynchronous version of `Client::retry`. If `requests` is set (i.e.
+    /// `f` sends a request), every attempt, including the first, waits for the
+    /// host to be unpaused and for the requests per second limit.
+    fn retry<T, F>(
+        &self,
+        url: Url,
+        path: PathBuf,
+        requests: bool,
+        f: F,
+    ) -> BoxFuture<(Result<T>, usize)>
+    where
+        T: 'static,
+        F: Fn(&AsyncClient) -> BoxFuture<T> + 'static,
+    {
+        let client = self.clone();
+        let f = Rc::new(f);
+        let future = future::loop_fn(1, move |attempt| {
+            let retry_client = client.clone();
+            let f = f.clone();
+            let url = url.clone();
+            let path = path.clone();
+            let wait = if requests {
+                client.wait_for_request(url.clone())
+            } else {
+                Box::new(future::ok(()))
+            };
+            wait.and_then(move |()| f(&retry_client).then(move |result| Ok((retry_client, result))))
+                .and_then(move |(client, result)| {
+                    let error = match result {
+                        Ok(value) => {
+                            return Either::A(future::ok(Loop::Break((Ok(value), attempt))))
+                        },
+                        Err(error) => error,
+                    };
+                    let backoff =
+                        client
+                            .retry_policy
+                            .next_backoff(&client.semaphore, &url, attempt, &error);
+                    match backoff {
+                        Some(backoff) => {
+                            client
+                                .observer
+                                .on_retried(&url, &path, attempt, &error, backoff);
+                            Either::B(
+                                client
+                                    .sleep(backoff)
+                                    .map(move |()| Loop::Continue(attempt + 1)),
+                            )
+                        },
+                        None => Either::A(future::ok(Loop::Break((Err(error), attempt)))),
+                    }
+                })
+        });
+        Box::new(future)
+    }
+
+    /// Resolves once a request to `url` is allowed by the pause and requests
+    /// per second limit of its host, and then by the global requests per
+    /// second limit.
+    fn wait_for_request(&self, url: Url) -> BoxFuture<()> {
+        let client = self.clone();
+        let future = future::loop_fn((), move |()| {
+            let wait = match client.semaphore.pause_remaining(&url) {
+                Some(remaining) => Loop::Continue(remaining),
+                None => Loop::Break(client.semaphore.reserve_host_request(&url)),
+            };
+            match wait {
+                Loop::Continue(remaining) => {
+                    Either::A(client.sleep(remaining).map(|()| Loop::Continue(())))
+                },
+                Loop::Break(wait) => Either::B(client.sleep(wait).map(|()| Loop::Break(()))),
+            }
+        });
+        let client = self.clone();
+        let future =
+            future.and_then(move |()| client.sleep(client.semaphore.reserve_global_request()));
+        Box::new(future)
+    }
+
+    /// Fails `inner` with a `TimeoutError` of `kind` if it doesn't resolve
+    /// within `timeout`, dropping it (which aborts a request or kills a
+    /// converter process).
+    fn with_timeout<F>(
+        &self,
+        inner: F,
+        kind: TimeoutKind,
+        timeout: Option<Duration>,
+    ) -> BoxFuture<F::Item>
+    where
+        F: Future<Error = Error> + 'static,
+    {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return Box::new(inner),
+        };
+        let timer = match Timeout::new(timeout, &self.handle) {
+            Ok(timer) => timer,
+            Err(e) => return Box::new(future::err(e.into())),
+        };
+        let future = inner.select2(timer).then(move |result| match result {
+            Ok(Either::A((item, _))) => Ok(item),
+            Ok(Either::B(_)) => Err(TimeoutError::new(kind, timeout).into()),
+            Err(Either::A((e, _))) => Err(e),
+            Err(Either::B((e, _))) => Err(e.into()),
+        });
+        Box::new(future)
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+        if duration == Duration::from_secs(0) {
+            return Box::new(future::ok(()));
+        }
+        match Timeout::new(duration, &self.handle) {
+            Ok(timeout) => Box::new(timeout.from_err()),
+            Err(e) => Box::new(future::err(e.into())),
+        }
+    }
+
+    /// Sends `request` and streams the response body to `<path>.part`, where
+    /// `path` is the path of `target`, chunk by chunk, resuming an earlier
+    /// partial download if there is one, and renames it to `path` once it is
+    /// complete. Progress is reported against the `observed_path` of `target`.
+    /// If `validators` are given, the request is conditional and a `304 Not
+    /// Modified` leaves `path` as is (only `GET` requests are conditional or
+    /// resumed).
+    fn get_url(
+        &self,
+        target: Target,
+        request: Request,
+        validators: Option<Validators>,
+        expected_digest: Option<Digest>,
+        timeouts: Timeouts,
+    ) -> BoxFuture<Fetched> {
+        let client = self.clone();
+        let handle = self.handle.clone();
+        let limits = self.limits.clone();
+        let refresh_policy = self.refresh_policy;
+        let (path, observed_path) = (target.path, target.observed_path);
+        let part_file = PartFile::new(&path);
+        let (validators, resume) = if request.is_get() {
+            (validators, part_file.resume())
+        } else {
+            (None, None)
+        };
+        let url = request.url.clone();
+        let observer = self.observer.clone();
+        let future = self
+            .send(&request, validators.as_ref(), resume.as_ref(), &timeouts)
+            .and_then({
+                let part_file = part_file.clone();
+                let validators = validators.clone();
+                move |response| {
+                    if response.status() == StatusCode::RangeNotSatisfiable && resume.is_some() {
+                        part_file.discard();
+                        let future = client.send(&request, validators.as_ref(), None, &timeouts);
+                        Either::A(future.map(|response| (response, None)))
+                    } else {
+                        Either::B(future::ok((response, resume)))
+                    }
+                }
+            })
+            .and_then(move |(response, resume)| {
+                let status = response.status();
+                let headers = response.headers().clone();
+                let info = ResponseInfo::new(response.url().clone(), Some(status), headers.clone());
+                if status == StatusCode::NotModified && validators.is_some() {
+                    refresh_policy.store(&path, &headers, validators.as_ref())?;
+                    return Ok(Either::A(future::ok(Fetched {
+                        outcome: Outcome::NotModified,
+                        response: info,
+                        bytes_written: None,
+                    })));
+                }
+                let (file, offset) = part_file.open(status, &headers, resume.as_ref())?;
+                if let Err(e) = limits.check_headers(&headers, offset) {
+                    part_file.discard();
+                    return Err(e);
+                }
+                let content_length = headers
+                    .get::<ContentLength>()
+                    .map(|length| offset + length.0);
+                let part = if offset > 0 {
+                    Some(part_file.path())
+                } else {
+                    None
+                };
+                let writer =
+                    HashingWriter::new(BufWriter::new(file), expected_digest.as_ref(), part)?;
+                let (fold_url, fold_path) = (url.clone(), observed_path);
+                let body = ReadTimeout {
+                    inner: response.into_body().from_err(),
+                    handle,
+                    timeout: timeouts.read(),
+                    timer: None,
+                };
+                let future = body
+                    .fold(
+                        (writer, offset),
+                        move |(mut writer, bytes_written), chunk| {
+                            writer.write_all(&chunk)?;
+                            let bytes_written = bytes_written + chunk.len() as u64;
+                            observer.on_bytes_received(
+                                &fold_url,
+                                &fold_path,
+                                bytes_written,
+                                content_length,
+                            );
+                            limits.check_size(bytes_written)?;
+                            Ok::<_, Error>((writer, bytes_written))
+                        },
+                    )
+                    .and_then(move |(mut writer, bytes_written)| {
+                        writer.flush()?;
+                        let digest = writer.finish();
+                        if let (Some(expected_digest), Some(digest)) = (expected_digest, digest) {
+                            expected_digest.verify(digest)?;
+                        }
+                        Ok(bytes_written)
+                    })
+                    .map_err({
+                        let part_file = part_file.clone();
+                        move |e| {
+                            // There is no point in resuming a body that was rejected or
+                            // doesn't have the expected digest.
+                            if e.downcast_ref::<RejectedError>().is_some()
+                                || e.downcast_ref::<DigestMismatch>().is_some()
+                            {
+                                part_file.discard();
+                            }
+                            e
+                        }
+                    })
+                    .and_then(move |bytes_written| {
+                        part_file.persist()?;
+                        refresh_policy.store(&path, &headers, None)?;
+                        Ok(Fetched {
+                            outcome: Outcome::Downloaded,
+                            response: info,
+                            bytes_written: Some(bytes_written),
+                        })
+                    });
+                Ok(Either::B(future))
+            })
+            .flatten();
+        self.with_timeout(future, TimeoutKind::Total, timeouts.total())
+    }
+
+    fn send(
+        &self,
+        request: &Request,
+        validators: Option<&Validators>,
+        resume: Option<&Resume>,
+        timeouts: &Timeouts,
+    ) -> BoxFuture<reqwest_async::Response> {
+        let mut builder = self
+            .inner
+            .request(request.method.clone(), request.url.clone());
+        builder.headers(request.headers.clone());
+        if let Some(ref body) = request.body {
+            // Unlike the blocking client, the async client doesn't set the
+            // length of the body, so it would be sent chunked.
+            builder.header(ContentLength(body.len() as u64));
+            builder.body(body.clone());
+        }
+        if let Some(validators) = validators {
+            builder.headers(validators.conditional_headers());
+        }
+        if let Some(resume) = resume {
+            builder.headers(resume.headers());
+        }
+        self.with_timeout(
+            builder.send().from_err(),
+            TimeoutKind::Connect,
+            timeouts.connect(),
+        )
+    }
+
+    /// Converts the document into `path` with `converter`, which sends
+    /// `request` itself unless the document was already downloaded to
+    /// `source`, along with the `ResponseInfo` of that download. The
+    /// downloaded HTML is fed to the converter behind a `<base>` tag, so that
+    /// relative links still resolve against the URL.
+    fn get_converted(
+        &self,
+        path: PathBuf,
+        converter: Converter,
+        request: Request,
+        settings: wkhtmltopdf::Settings,
+        timeouts: Timeouts,
+        source: Option<(PathBuf, Option<ResponseInfo>)>,
+    ) -> BoxFuture<Fetched> {
+        let client = self.clone();
+        let url = request.url.clone();
+        let response = match source {
+            Some((_, Some(ref response))) => response.clone(),
+            _ => ResponseInfo::new(url.clone(), None, Headers::new()),
+        };
+        let future = AcquireCpu::new(&self.semaphore).and_then(move |permit| {
+            let temp_file = match client.temp_files.create(&path) {
+                Ok(temp_file) => temp_file,
+                Err(e) => return Either::A(future::err(e)),
+            };
+            let command = match source {
+                Some((ref source, _)) => client
+                    .based_source(response.url(), source, &path)
+                    .and_then(|based_source| {
+                        let mut command =
+                            converter.to_stdin_command(&settings, temp_file.path())?;
+                        command.stdin(Stdio::from(File::open(based_source.path())?));
+                        Ok((command, Some(based_source)))
+                    }),
+                None => converter
+                    .to_command(&settings, &request, temp_file.path())
+                    .map(|command| (command, None)),
+            };
+            let (mut command, based_source) = match command {
+                Ok(command) => command,
+                Err(e) => return Either::A(future::err(e)),
+            };
+            let start = Instant::now();
+            client.observer.on_conversion_started(&url, &path);
+            let status = match command.status_async_with_handle(client.handle.new_tokio_handle()) {
+                Ok(status) => status,
+                Err(e) => {
+                    client.observer.on_conversion_finished(&url, &path, false);
+                    return Either::A(future::err(e.into()));
+                },
+            };
+            let status = client.with_timeout(
+                status.from_err(),
+                TimeoutKind::Conversion,
+                timeouts.conversion(),
+            );
+            Either::B(status.then(move |result| {
+                let exit_status = match result {
+                    Ok(exit_status) => exit_status,
+                    Err(e) => {
+                        client.observer.on_conversion_finished(&url, &path, false);
+                        return Err(e);
+                    },
+                };
+                drop(permit);
+                drop(based_source);
+                client
+                    .observer
+                    .on_conversion_finished(&url, &path, exit_status.success());
+                if !exit_status.success() {
+                    return Err(ConversionError::new(exit_status.code()).into());
+                }
+                let bytes_written = fs::metadata(temp_file.path())?.len();
+                temp_file.persist()?;
+                client.refresh_policy.store(&path, &Headers::new(), None)?;
+                Ok(Fetched {
+                    outcome: Outcome::Converted,
+                    response: response.converted(start.elapsed()),
+                    bytes_written: Some(bytes_written),
+                })
+            }))
+        });
+        Box::new(future)
+    }
+
+    /// Writes the document downloaded to `source` with a `<base>` tag for
+    /// `url` (the URL it was downloaded from, after redirects) to a temp file
+    /// next to `path`, to be fed to a converter.
+    fn based_source(&self, url: &Url, source: &Path, path: &Path) -> Result<TempFile> {
+        let temp_file = self.temp_files.create(path)?;
+        let file = BufWriter::new(File::create(temp_file.path())?);
+        converter::write_with_base(url, File::open(source)?, file)?;
+        Ok(temp_file)
+    }
+}
+
+/// Resolves to a `HostPermit` once the host of `url` has a free concurrent
+/// request slot.
+struct AcquireHost {
+    semaphore: Arc<Semaphore>,
+    url: Url,
+    waiter: usize,
+}
+
+impl AcquireHost {
+    fn new(semaphore: &Arc<Semaphore>, url: Url) -> Self {
+        AcquireHost {
+            semaphore: semaphore.clone(),
+            url,
+            waiter: semaphore.waiter(),
+        }
+    }
+}
+
+impl Future for AcquireHost {
+    type Item = HostPermit;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<HostPermit, Error> {
+        match self.semaphore.poll_acquire_host(&self.url, self.waiter) {
+            Some(permit) => Ok(Async::Ready(permit)),
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl Drop for AcquireHost {
+    fn drop(&mut self) {
+        self.semaphore.forget_waiter(self.waiter);
+    }
+}
+
+/// A `Stream` that fails with a read `TimeoutError` once its inner stream has
+/// yielded nothing for `timeout`.
+struct ReadTimeout<S> {
+    inner: S,
+    handle: Handle,
+    timeout: Option<Duration>,
+    timer: Option<Timeout>,
+}
+
+impl<S: Stream<Error = Error>> Stream for ReadTimeout<S> {
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, Error> {
+        let item = self.inner.poll()?;
+        if item.is_ready() {
+            self.timer = None;
+            return Ok(item);
+        }
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Ok(Async::NotReady),
+        };
+        if self.timer.is_none() {
+            self.timer = Some(Timeout::new(timeout, &self.handle)?);
+        }
+        match self.timer.as_mut().map(|timer| timer.poll()) {
+            Some(Ok(Async::NotReady)) => Ok(Async::NotReady),
+            Some(Err(e)) => Err(e.into()),
+            _ => Err(TimeoutError::new(TimeoutKind::Read, timeout).into()),
+        }
+    }
+}
+
+/// Resolves to a `CpuPermit` once a cpu thread slot is free.
+struct AcquireCpu {
+    semaphore: Arc<Semaphore>,
+    waiter: usize,
+}
+
+impl AcquireCpu {
+    fn new(semaphore: &Arc<Semaphore>) -> Self {
+        AcquireCpu {
+            semaphore: semaphore.clone(),
+            waiter: semaphore.waiter(),
+        }
+    }
+}
+
+impl Future for AcquireCpu {
+    type Item = CpuPermit;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<CpuPermit, Error> {
+        match self.semaphore.poll_increment_threads_cpu(self.waiter) {
+            Some(permit) => Ok(Async::Ready(permit)),
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl Drop for AcquireCpu {
+    fn drop(&mut self) {
+        self.semaphore.forget_waiter(self.waiter);
+    }
+}
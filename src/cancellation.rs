@@ -0,0 +1,136 @@
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use error::{CancelledError, Result};
+
+/// How often a thread that waits checks whether its token was cancelled
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A `CancellationToken` stops a running `Client::get_documents_with_cancel`
+/// from the outside (e.g. from a SIGINT handler). Once `cancel` is called, no
+/// new document is started and documents in flight are aborted: downloads
+/// stop at the next chunk and keep what they have in `<path>.part` for the
+/// next run, conversions are killed and their temp files removed. Clones share
+/// the same state, so a clone can be handed to another thread.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a `CancellationToken` that is not cancelled
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancels the token (and all of its clones)
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the token has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns a `CancelledError` if the token has been cancelled.
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(CancelledError.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sleeps for `duration`, but returns a `CancelledError` as soon as the
+    /// token is cancelled.
+    pub(crate) fn sleep(&self, duration: Duration) -> Result<()> {
+        let deadline = Instant::now() + duration;
+        loop {
+            self.check()?;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Ok(());
+            }
+            thread::sleep(cmp::min(remaining, POLL_INTERVAL));
+        }
+    }
+
+    /// Same as `io::copy`, but checks the token before every chunk and calls
+    /// `progress` with the number of bytes copied so far after every chunk,
+    /// stopping with its error if it returns one.
+    pub(crate) fn copy<R, W, F>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        mut progress: F,
+    ) -> Result<u64>
+    where
+        R: Read,
+        W: Write,
+        F: FnMut(u64) -> Result<()>,
+    {
+        let mut buffer = [0; 8 * 1024];
+        let mut bytes_copied = 0;
+        loop {
+            self.check()?;
+            let n = match reader.read(&mut buffer) {
+                Ok(0) => return Ok(bytes_copied),
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            writer.write_all(&buffer[..n])?;
+            bytes_copied += n as u64;
+            progress(bytes_copied)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy() {
+        let token = CancellationToken::new();
+        let mut writer = Vec::new();
+        let mut progress = 0;
+        let bytes_copied = token
+            .copy(&mut &b"0123"[..], &mut writer, |n| {
+                progress = n;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(4, bytes_copied);
+        assert_eq!(4, progress);
+        assert_eq!(b"0123".to_vec(), writer);
+
+        token.clone().cancel();
+        let error = token
+            .copy(&mut &b"0123"[..], &mut writer, |_| Ok(()))
+            .unwrap_err();
+        assert!(error.downcast_ref::<CancelledError>().is_some());
+    }
+
+    #[test]
+    fn test_sleep() {
+        let token = CancellationToken::new();
+        let start = Instant::now();
+        assert!(token.sleep(Duration::from_millis(100)).is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        let canceller = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            canceller.cancel();
+        });
+        let start = Instant::now();
+        assert!(token.sleep(Duration::from_secs(60)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -1,163 +1,1284 @@
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::cmp;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use std::sync::mpsc::{channel, TryRecvError};
-use std::sync::Arc;
+use std::process::Command;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crossbeam;
+use reqwest::header::{ContentLength, Headers};
 use reqwest::{self, StatusCode};
 use url::Url;
 
+use cancellation::CancellationToken;
+use converter::{self, Converter};
+use digest::{self, Digest, HashingWriter};
 use document::Document;
-use error::{Error, Result};
-use semaphore::Semaphore;
+use error::{
+    duplicate_error, CancelledError, ConversionError, DigestMismatch, Error, RejectedError, Result,
+    StatusError, TimeoutError, TimeoutKind,
+};
+use limits::Limits;
+use observer::Observer;
+use output::Output;
+use output_format::OutputFormat;
+use part_file::PartFile;
+use refresh::{Freshness, RefreshPolicy};
+use report::{BatchReport, DocumentReport, Outcome};
+use request::{Request, RequestKey};
+use response_info::ResponseInfo;
+use retry::{retry_after, RetryPolicy};
+use scheduler::{Pool, Scheduler};
+use semaphore::{HostPermit, Semaphore};
+use temp_file::TempFiles;
+use timeouts::Timeouts;
+use utils::read_file;
+use validators::Validators;
 use wkhtmltopdf;
 
 /// A `Client` downloads and writes to disk a slice of boxed objects
 /// implementing `Document`. It does this in parallel to maximize efficiency,
 /// but will never exceed the maximum number of requests per second provided by
-/// the user nor the maximum number of threads provided.  Additionally, if the
-/// object implemeting `Document` returns `true` from its `wkhtmltopdf()` method,
-/// the `Client` will use `wkhtmltopdf` to convert what it downloads to PDF before
-/// writing it to disk.
+/// the user nor the maximum number of threads provided.  Additionally, unless the
+/// object implemeting `Document` returns `OutputFormat::Raw` from its
+/// `output_format()` method, the `Client` will convert what it downloads (e.g.
+/// to PDF with `wkhtmltopdf`) before writing it to disk.
 #[derive(Clone, Debug)]
 pub struct Client {
-    pub(crate) inner: reqwest::Client,
+    pub(crate) inner: Arc<ReqwestClients>,
+    pub(crate) keep_bytes: bool,
+    pub(crate) limits: Limits,
+    pub(crate) max_threads_cpu: usize,
+    pub(crate) max_threads_io: usize,
+    pub(crate) observer: Observer,
+    pub(crate) refresh_policy: RefreshPolicy,
+    pub(crate) retry_policy: RetryPolicy,
     pub(crate) semaphore: Arc<Semaphore>,
+    pub(crate) temp_files: Arc<TempFiles>,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) verify_existing_digests: bool,
     pub(crate) wkhtmltopdf_settings: wkhtmltopdf::Settings,
 }
 
+/// The blocking `reqwest::Client`s of a `Client`. A blocking client applies a
+/// single timeout, fixed when it is built, to connecting and to every read, so
+/// there is one client per timeout, shared by every document that uses it.
+#[derive(Debug)]
+pub(crate) struct ReqwestClients {
+    custom: Option<reqwest::Client>,
+    clients: Mutex<HashMap<Option<Duration>, reqwest::Client>>,
+}
+
+impl ReqwestClients {
+    pub(crate) fn new(custom: Option<reqwest::Client>) -> Self {
+        ReqwestClients {
+            custom,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the client for the connect and read timeouts of `timeouts`, or
+    /// the custom client if one was provided.
+    pub(crate) fn get(&self, timeouts: &Timeouts) -> Result<reqwest::Client> {
+        if let Some(ref custom) = self.custom {
+            return Ok(custom.clone());
+        }
+        let timeout = timeouts.io();
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&timeout) {
+            return Ok(client.clone());
+        }
+        let client = reqwest::ClientBuilder::new()
+            .gzip(false)
+            .timeout(timeout)
+            .build()?;
+        clients.insert(timeout, client.clone());
+        Ok(client)
+    }
+}
+
+/// A `DocumentIter` is the `Iterator` returned by `Client::get_documents_iter`.
+/// It yields every document along with its `DocumentReport` in the order the
+/// documents finish. Dropping it cancels the documents in flight and stops
+/// pulling documents from the input.
+pub struct DocumentIter<D> {
+    cancel: CancellationToken,
+    receiver: Receiver<(D, DocumentReport)>,
+    scheduler: Arc<Scheduler<D>>,
+}
+
+impl<D> fmt::Debug for DocumentIter<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DocumentIter").finish()
+    }
+}
+
+impl<D> Iterator for DocumentIter<D> {
+    type Item = (D, DocumentReport);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl<D> Drop for DocumentIter<D> {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+        self.scheduler.abort();
+    }
+}
+
+/// One of the `Document::outputs` of a document, which is processed like a
+/// document of its own that shares the request of the document
+#[derive(Clone, Debug)]
+pub(crate) struct Target {
+    /// The position of the output in `Document::outputs`
+    pub(crate) index: usize,
+    pub(crate) path: PathBuf,
+    /// The path the observer is told about: `path`, except for the temp file
+    /// a group is downloaded to before it is converted, which is reported as
+    /// the output it is converted to
+    pub(crate) observed_path: PathBuf,
+    pub(crate) format: OutputFormat,
+    /// Whether the document is handed the bytes and `ResponseInfo` of this
+    /// output through `Document::set_bytes` and `Document::set_response`,
+    /// which is the case for its first output only
+    pub(crate) first: bool,
+}
+
 impl Client {
     /// Downloads documents and writes them to disk. If the document already
-    /// exists on disk `get_documents` will not redownload it
-    pub fn get_documents<D>(&self, documents: &mut [Box<D>]) -> Result<()>
+    /// exists on disk `get_documents` will not redownload it, unless the
+    /// client's `RefreshPolicy` says it has to be revalidated. Response bodies
+    /// are streamed straight to disk; each document is then handed its bytes
+    /// through `set_bytes`, or `None` if the client was built with
+    /// `set_keep_bytes(false)`. Every document is written to a temp file in
+    /// the same directory and only renamed to its path once it is complete, so
+    /// an interrupted run never leaves a truncated document behind. For
+    /// downloads that temp file is `<path>.part`, which is kept on failure and
+    /// resumed with a `Range` request by the next attempt or run (or restarted
+    /// if the server ignores the range or the document has changed).
+    ///
+    /// Documents are processed by two fixed pools of threads that take them
+    /// from a queue: `max_threads_io` threads download documents and
+    /// `max_threads_cpu` threads convert documents (see `OutputFormat`), so the
+    /// number of threads doesn't grow with the number of documents. Documents
+    /// of a higher `Document::priority` are started first; documents of the
+    /// same priority are started in the order of `documents`, both pools
+    /// working through them side by side. A pool whose workers are all busy
+    /// doesn't keep the other pool from starting documents of a lower
+    /// priority. `documents` itself is not reordered.
+    /// A document whose host has no free concurrent request slot, is paused
+    /// (see `RetryPolicy::set_max_retry_after`) or has reached its requests
+    /// per second limit is passed over for the documents of other hosts until
+    /// its host is ready, rather than tying up a worker.
+    ///
+    /// Documents that share a request (the same URL, `Document::method`,
+    /// `Document::headers` and `Document::body`) and the same
+    /// `Document::expected_digest` and `Document::timeouts` are fetched only
+    /// once: the response is written to the path of every raw document with
+    /// that request, and the documents to be converted are converted from it
+    /// (once per format) instead of having the converter fetch the URL again.
+    /// The same goes for the `Document::outputs` of a single document.
+    ///
+    /// Rather than stopping at the first failure, `get_documents` processes every
+    /// document and returns a `BatchReport` containing one `DocumentReport` per
+    /// document, in the same order as `documents`.
+    ///
+    /// `documents` may hold documents of different types as
+    /// `Box<dyn Document + Send>`; they all share the same limits.
+    pub fn get_documents<D>(&self, documents: &mut [Box<D>]) -> Result<BatchReport>
     where
-        D: Document + Send,
+        D: Document + Send + ?Sized,
     {
-        let results = crossbeam::scope(|scope| {
-            let (s1, r1) = channel();
-            let (s2, r2) = channel();
-
-            let semaphore = (self.semaphore).clone();
-            scope.spawn(move || {
-                loop {
-                    thread::sleep(Duration::from_millis(1000));
-                    semaphore.reset_requests();
-                    match r1.try_recv() {
-                        Ok(_) | Err(TryRecvError::Disconnected) => break,
-                        Err(TryRecvError::Empty) => (),
-                    }
-                }
-            });
+        self.get_documents_with_cancel(documents, &CancellationToken::new())
+    }
 
-            documents.sort_by(|a, b| a.wkhtmltopdf().cmp(&b.wkhtmltopdf()));
+    /// Same as `get_documents`, but stops early once `cancel` is cancelled: no
+    /// new document is started, documents in flight are aborted (and reported
+    /// as failed with a `CancelledError`) and the documents that were never
+    /// started are reported as `Outcome::NotAttempted`. Documents are only ever
+    /// renamed into place once they are complete, so cancelling doesn't leave
+    /// half-written documents behind.
+    pub fn get_documents_with_cancel<D>(
+        &self,
+        documents: &mut [Box<D>],
+        cancel: &CancellationToken,
+    ) -> Result<BatchReport>
+    where
+        D: Document + Send + ?Sized,
+    {
+        for document in documents.iter() {
+            self.observer.on_queued(document.url(), document.path());
+        }
 
-            let mut children = Vec::new();
-            for document in documents.iter_mut() {
-                let path = PathBuf::from(document.path());
-                let url = document.url().clone();
-                let wkhtmltopdf = document.wkhtmltopdf();
-                if path.exists() {
-                    let result = File::open(path).map_err(Error::from).and_then(|file| {
-                        let mut reader = BufReader::new(file);
-                        let mut bytes = Vec::new();
-                        reader.read_to_end(&mut bytes)?;
-                        trace!("processed {:?}", &url);
-                        (*document).set_bytes(Some(bytes));
-                        Ok::<_, Error>(())
-                    });
-                    s2.send(result).unwrap();
-                    continue;
+        let mut reports = documents.iter().map(|_| None).collect::<Vec<_>>();
+        // Documents that share a request (URL, method, headers and body), an
+        // expected digest and timeouts are fetched once, as a group.
+        let mut groups: Vec<Vec<(usize, &mut D)>> = Vec::new();
+        let mut groups_by_request: HashMap<_, usize> = HashMap::new();
+        for (index, document) in documents.iter_mut().enumerate() {
+            let document = &mut **document;
+            match groups_by_request.entry(group_key(document)) {
+                Entry::Occupied(entry) => groups[*entry.get()].push((index, document)),
+                Entry::Vacant(entry) => {
+                    entry.insert(groups.len());
+                    groups.push(vec![(index, document)]);
+                },
+            }
+        }
+        let scheduler = Scheduler::unbounded(self.semaphore.clone());
+        let (mut len_cpu, mut len_io) = (0, 0);
+        for group in groups {
+            let url = group[0].1.url().clone();
+            let pool = if group.iter().any(|(_, document)| is_converted(&**document)) {
+                len_cpu += 1;
+                Pool::Cpu
+            } else {
+                len_io += 1;
+                Pool::Io
+            };
+            let priority = group
+                .iter()
+                .map(|(_, document)| document.priority())
+                .max()
+                .unwrap_or_default();
+            // Pushing only fails once the scheduler is aborted, which it never is here.
+            let _ = scheduler.push(pool, priority, &url, group);
+        }
+        scheduler.close();
+        let pools = [
+            (Pool::Cpu, self.max_threads_cpu, len_cpu),
+            (Pool::Io, self.max_threads_io, len_io),
+        ];
+        crossbeam::scope(|scope| {
+            let (sender, receiver) = channel();
+            for &(pool, max_threads, len) in &pools {
+                // No pool needs more workers than it has groups.
+                let workers = cmp::min(max_threads, len);
+                for _ in 0..workers {
+                    let sender = sender.clone();
+                    let scheduler = &scheduler;
+                    scope.spawn(move || self.work(scheduler, pool, &sender, cancel));
                 }
+            }
+            drop(sender);
+            for (index, report) in receiver {
+                reports[index] = Some(report);
+            }
+        });
+        let reports = reports
+            .into_iter()
+            .map(|report| report.expect("every document produces a report"))
+            .collect();
+        Ok(BatchReport::new(reports))
+    }
 
+    /// Same as `get_documents`, but takes anything that can be turned into an
+    /// iterator of documents and returns a `DocumentIter` that yields each
+    /// document along with its `DocumentReport` as soon as it is finished.
+    /// Documents are pulled from `documents` on a background thread as the
+    /// worker pools free up, so only the documents in flight are held in
+    /// memory and the input may be unbounded (e.g. rows from a database
+    /// cursor). As a consequence, `Document::priority` only orders the
+    /// documents that have been pulled but not started yet, and documents that
+    /// share a URL are fetched once each.
+    pub fn get_documents_iter<I, D>(&self, documents: I) -> DocumentIter<D>
+    where
+        I: IntoIterator<Item = D>,
+        I::IntoIter: Send + 'static,
+        D: Document + Send + 'static,
+    {
+        let cancel = CancellationToken::new();
+        let (max_threads_cpu, max_threads_io) = (self.max_threads_cpu, self.max_threads_io);
+        let (sender, receiver) = sync_channel(max_threads_cpu + max_threads_io);
+        let scheduler = Arc::new(Scheduler::<D>::bounded(
+            self.semaphore.clone(),
+            max_threads_cpu,
+            max_threads_io,
+        ));
+        for &(pool, max_threads) in &[(Pool::Cpu, max_threads_cpu), (Pool::Io, max_threads_io)] {
+            for _ in 0..max_threads {
                 let client = self.clone();
-                let s2 = s2.clone();
-                self.semaphore.increment_requests();
-                if wkhtmltopdf {
-                    self.semaphore.increment_threads_cpu();
-                    let child = scope.spawn(move || {
-                        let result = self.get_pdf(&path, &url).and_then(|bytes| {
-                            document.set_bytes(Some(bytes));
-                            info!("downloaded {:?}", &url);
-                            Ok::<_, Error>(())
-                        });
-                        s2.send(result).unwrap();
-                        client.semaphore.decrement_threads_cpu();
-                    });
-                    children.push(child);
+                let scheduler = scheduler.clone();
+                let sender = sender.clone();
+                let cancel = cancel.clone();
+                thread::spawn(move || {
+                    while let Some((mut document, permit)) = scheduler.pop(pool, &cancel) {
+                        let report = client.get_document(&mut document, permit, &cancel);
+                        scheduler.release(document.url());
+                        client.observer.on_finished(&report);
+                        if sender.send((document, report)).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        }
+
+        let documents = documents.into_iter();
+        let client = self.clone();
+        let feeder_cancel = cancel.clone();
+        let feeder_scheduler = scheduler.clone();
+        thread::spawn(move || {
+            for document in documents {
+                if feeder_cancel.is_cancelled() {
+                    return;
+                }
+                client.observer.on_queued(document.url(), document.path());
+                let pool = if is_converted(&document) {
+                    Pool::Cpu
                 } else {
-                    self.semaphore.increment_threads_io();
-                    let child = scope.spawn(move || {
-                        let result = client.get_url(&url);
-                        let result = result.and_then(|bytes| {
-                            let file = File::create(&path)?;
-                            let mut writer = BufWriter::new(file);
-                            writer.write_all(&bytes)?;
-                            info!("downloaded {:?}", &url);
-                            document.set_bytes(Some(bytes));
-                            Ok::<_, Error>(())
-                        });
-                        s2.send(result).unwrap();
-                        client.semaphore.decrement_threads_io();
-                    });
-                    children.push(child);
+                    Pool::Io
+                };
+                let priority = document.priority();
+                let url = document.url().clone();
+                if feeder_scheduler
+                    .push(pool, priority, &url, document)
+                    .is_err()
+                {
+                    return;
                 }
             }
-            let mut results = Vec::new();
-            for _ in children {
-                let result = r2.recv().unwrap();
-                results.push(result);
+            feeder_scheduler.close();
+        });
+        DocumentIter {
+            cancel,
+            receiver,
+            scheduler,
+        }
+    }
+
+    /// Runs one worker of `pool`: takes groups of documents from `scheduler`
+    /// until its queue is empty and sends the report of every document to
+    /// `sender`.
+    fn work<D>(
+        &self,
+        scheduler: &Scheduler<Vec<(usize, &mut D)>>,
+        pool: Pool,
+        sender: &Sender<(usize, DocumentReport)>,
+        cancel: &CancellationToken,
+    ) where
+        D: Document + ?Sized,
+    {
+        while let Some((group, permit)) = scheduler.pop(pool, cancel) {
+            let url = group[0].1.url().clone();
+            let reports = self.get_group(group, permit, cancel);
+            scheduler.release(&url);
+            for (index, report) in reports {
+                self.observer.on_finished(&report);
+                sender.send((index, report)).unwrap();
             }
+        }
+    }
 
-            s1.send(()).unwrap();
-            results
+    /// Fetches a group of documents that share a request and returns the
+    /// report of every document, which holds the reports of its other
+    /// `Document::outputs`. The request is sent once, by the first raw output
+    /// that isn't fresh, and the response copied to the paths of the other raw
+    /// outputs; the outputs to be converted are converted from that download
+    /// once per distinct `OutputFormat` and `wkhtmltopdf::Settings` and copied
+    /// likewise. Without a raw output, a single conversion has the converter
+    /// send the request, while several (or one that is subject to limits or
+    /// an expected digest, see `converts_from_download`) are converted from a
+    /// download to a temp file. The shared fetch holds `permit`, the host slot
+    /// the group was started with, counts against the requests per second
+    /// limits once, and uses the timeouts every document of the group shares.
+    fn get_group<D>(
+        &self,
+        mut group: Vec<(usize, &mut D)>,
+        permit: HostPermit,
+        cancel: &CancellationToken,
+    ) -> Vec<(usize, DocumentReport)>
+    where
+        D: Document + ?Sized,
+    {
+        let mut members = Vec::new();
+        for (position, (_, document)) in group.iter().enumerate() {
+            for target in targets(&**document) {
+                members.push((position, target));
+            }
+        }
+        let single = members.len() == 1
+            && (members[0].1.format == OutputFormat::Raw
+                || !converts_from_download(&self.limits, &*group[members[0].0].1));
+        let reports = if single {
+            let (position, target) = members.remove(0);
+            let report = self.get_target(&mut *group[position].1, &target, permit, cancel);
+            vec![(position, target.index, report)]
+        } else {
+            self.get_targets(&mut group, members, permit, cancel)
+        };
+
+        let mut outputs = group.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+        for (position, index, report) in reports {
+            outputs[position].push((index, report));
+        }
+        group
+            .iter()
+            .zip(outputs)
+            .map(|((index, _), outputs)| (*index, combine(outputs)))
+            .collect()
+    }
+
+    /// Fetches `members`, the outputs of the documents of `group` (by their
+    /// position in it), as described in `get_group` and returns the report of
+    /// every output along with its position and its index in
+    /// `Document::outputs`.
+    fn get_targets<D>(
+        &self,
+        group: &mut [(usize, &mut D)],
+        members: Vec<(usize, Target)>,
+        mut permit: HostPermit,
+        cancel: &CancellationToken,
+    ) -> Vec<(usize, usize, DocumentReport)>
+    where
+        D: Document + ?Sized,
+    {
+        let mut reports = Vec::new();
+        let mut raw = Vec::new();
+        let mut to_convert = Vec::new();
+        for (position, target) in members {
+            let document = &mut *group[position].1;
+            if cancel.is_cancelled() {
+                reports.push((position, target.index, not_attempted(document, &target)));
+                continue;
+            }
+            let validators = match self.freshness(document, &target) {
+                Freshness::Missing => None,
+                Freshness::Stale(validators) => validators,
+                Freshness::Fresh => {
+                    let report = self.skip_existing(document, &target);
+                    reports.push((position, target.index, report));
+                    continue;
+                },
+            };
+            match Converter::new(target.format) {
+                Some(converter) => {
+                    let settings = document
+                        .wkhtmltopdf_settings()
+                        .or(&self.wkhtmltopdf_settings);
+                    to_convert.push((position, target, converter, settings));
+                },
+                None => raw.push((position, target, validators)),
+            }
+        }
+        if raw.is_empty() && to_convert.is_empty() {
+            return reports;
+        }
+        let url = group[0].1.url().clone();
+        // Every document of a group has the same timeouts.
+        let timeouts = group[0].1.timeouts().or(self.timeouts);
+
+        if permit.increment_requests(cancel).is_err() {
+            for (position, target, _) in raw {
+                let report = not_attempted(&*group[position].1, &target);
+                reports.push((position, target.index, report));
+            }
+            for (position, target, _, _) in to_convert {
+                let report = not_attempted(&*group[position].1, &target);
+                reports.push((position, target.index, report));
+            }
+            return reports;
+        }
+        let start = Instant::now();
+        for (_, target, _) in &raw {
+            self.observer.on_started(&url, &target.path);
+        }
+        for (_, target, _, _) in &to_convert {
+            self.observer.on_started(&url, &target.path);
+        }
+
+        let mut shared = Vec::new();
+        // A conditional request only makes sense if every output has the same
+        // version on disk.
+        let same_validators = raw.windows(2).all(|raw| raw[0].2 == raw[1].2);
+        let mut raw = raw.into_iter();
+        let downloaded = raw.next().map(|(position, target, validators)| {
+            let document = &mut *group[position].1;
+            let validators = if same_validators { validators } else { None };
+            let report = self.download(document, &target, validators.as_ref(), &timeouts, cancel);
+            for (position, target, _) in raw {
+                let document = &mut *group[position].1;
+                let shared_report = self.share(&report, document, &target);
+                shared.push((position, target.index, shared_report));
+            }
+            (position, target.index, report)
         });
-        for result in results {
-            result?;
+        // Without a raw output to convert from, the body is downloaded to a
+        // temp file once instead of having the converter of every distinct
+        // conversion fetch it (or of one that has to be checked first).
+        let mut conversions: Vec<(Converter, &wkhtmltopdf::Settings)> = Vec::new();
+        for (_, _, converter, settings) in &to_convert {
+            if !conversions.contains(&(*converter, settings)) {
+                conversions.push((*converter, settings));
+            }
+        }
+        let cache = match to_convert.first() {
+            Some((position, target, _, _)) if downloaded.is_none() => {
+                let document = &mut *group[*position].1;
+                if conversions.len() > 1 || converts_from_download(&self.limits, document) {
+                    let url = document.url().clone();
+                    let report = match self.temp_files.create(&target.path) {
+                        Ok(temp_file) => {
+                            let cache = Target {
+                                index: target.index,
+                                path: PathBuf::from(temp_file.path()),
+                                observed_path: target.path.clone(),
+                                format: OutputFormat::Raw,
+                                first: false,
+                            };
+                            let report = self.download(document, &cache, None, &timeouts, cancel);
+                            (Some(temp_file), report)
+                        },
+                        Err(e) => (None, DocumentReport::failed(url, target.path.clone(), e)),
+                    };
+                    Some(report)
+                } else {
+                    None
+                }
+            },
+            _ => None,
+        };
+        let source = downloaded
+            .as_ref()
+            .map(|(_, _, report)| report)
+            .or_else(|| cache.as_ref().map(|(_, report)| report));
+        // Outputs with the same converter and settings are converted once.
+        let mut converted: Vec<(usize, usize, DocumentReport, Converter, _)> = Vec::new();
+        for (position, target, converter, settings) in to_convert {
+            let document = &mut *group[position].1;
+            if let Some((_, _, report, _, _)) = converted
+                .iter()
+                .find(|(_, _, _, c, s)| *c == converter && *s == settings)
+            {
+                let report = self.share(report, document, &target);
+                shared.push((position, target.index, report));
+                continue;
+            }
+            let report = match source {
+                Some(source) if !source.is_ok() => self.share(source, document, &target),
+                _ => self.convert(document, &target, converter, source, &timeouts, cancel),
+            };
+            converted.push((position, target.index, report, converter, settings));
         }
-        Ok(())
+        if let Some((Some(temp_file), _)) = cache {
+            // The temp file itself is removed when it is dropped.
+            PartFile::new(temp_file.path()).discard();
+            if let Err(e) = self.refresh_policy.remove(temp_file.path()) {
+                warn!("{}", e);
+            }
+        }
+        drop(permit);
+
+        let converted = converted
+            .into_iter()
+            .map(|(position, index, report, _, _)| (position, index, report));
+        let elapsed = start.elapsed();
+        for (position, index, mut report) in downloaded.into_iter().chain(converted).chain(shared) {
+            report.elapsed = elapsed;
+            reports.push((position, index, report));
+        }
+        reports
     }
 
-    fn get_url(&self, url: &Url) -> Result<Vec<u8>> {
-        let mut response = self.inner.get(url.clone()).send()?;
-        match response.status() {
-            StatusCode::Ok => (),
-            status => bail!(format_err!("response status: {}", status)),
+    /// Fetches a single document, holding `permit`, and returns its report.
+    fn get_document<D>(
+        &self,
+        document: &mut D,
+        permit: HostPermit,
+        cancel: &CancellationToken,
+    ) -> DocumentReport
+    where
+        D: Document + ?Sized,
+    {
+        self.get_group(vec![(0, document)], permit, cancel)
+            .pop()
+            .map(|(_, report)| report)
+            .expect("every document produces a report")
+    }
+
+    /// Fetches (or converts) a single output of a document, holding `permit`,
+    /// once the requests per second limits allow it, and returns its report.
+    fn get_target<D>(
+        &self,
+        document: &mut D,
+        target: &Target,
+        mut permit: HostPermit,
+        cancel: &CancellationToken,
+    ) -> DocumentReport
+    where
+        D: Document + ?Sized,
+    {
+        if cancel.is_cancelled() {
+            return not_attempted(document, target);
+        }
+        let validators = match self.freshness(document, target) {
+            Freshness::Missing => None,
+            Freshness::Stale(validators) => validators,
+            Freshness::Fresh => return self.skip_existing(document, target),
+        };
+
+        let url = document.url().clone();
+        if permit.increment_requests(cancel).is_err() {
+            return not_attempted(document, target);
         }
-        let mut bytes = Vec::new();
-        response.read_to_end(&mut bytes)?;
-        Ok(bytes)
+        let timeouts = document.timeouts().or(self.timeouts);
+        let start = Instant::now();
+        self.observer.on_started(&url, &target.path);
+        let mut report = match Converter::new(target.format) {
+            Some(converter) => self.convert(document, target, converter, None, &timeouts, cancel),
+            None => self.download(document, target, validators.as_ref(), &timeouts, cancel),
+        };
+        report.elapsed = start.elapsed();
+        drop(permit);
+        report
     }
 
-    fn get_pdf<P: AsRef<Path>>(&self, path: P, url: &Url) -> Result<Vec<u8>> {
-        let mut arguments = self.wkhtmltopdf_settings.to_arguments();
-        arguments.push(url.to_string());
-        arguments.push(
-            path.as_ref()
-                .to_str()
-                .ok_or_else(|| format_err!("failed to parse path: {:?}", path.as_ref()))?
-                .to_string(),
+    fn freshness<D>(&self, document: &D, target: &Target) -> Freshness
+    where
+        D: Document + ?Sized,
+    {
+        freshness(
+            self.refresh_policy,
+            self.verify_existing_digests,
+            document,
+            target,
+        )
+    }
+
+    /// Returns the report of an output that exists on disk and is fresh,
+    /// handing the document its bytes.
+    fn skip_existing<D>(&self, document: &mut D, target: &Target) -> DocumentReport
+    where
+        D: Document + ?Sized,
+    {
+        let path = target.path.clone();
+        let url = document.url().clone();
+        let start = Instant::now();
+        let mut report = match read_bytes(self.keep_bytes && target.first, &path) {
+            Ok(bytes) => {
+                trace!("processed {:?}", &url);
+                self.observer.on_skipped_existing(&url, &path);
+                let response = self.refresh_policy.stored_response(&url, &path);
+                hand_over(document, target, bytes, response.as_ref());
+                let mut report = DocumentReport::new(url, path, Outcome::SkippedExisting);
+                report.response = response;
+                report
+            },
+            Err(e) => DocumentReport::failed(url, path, e),
+        };
+        report.elapsed = start.elapsed();
+        report
+    }
+
+    /// Downloads `document` to `target` (with retries) and returns its report.
+    fn download<D>(
+        &self,
+        document: &mut D,
+        target: &Target,
+        validators: Option<&Validators>,
+        timeouts: &Timeouts,
+        cancel: &CancellationToken,
+    ) -> DocumentReport
+    where
+        D: Document + ?Sized,
+    {
+        let path = target.path.clone();
+        let url = document.url().clone();
+        let request = Request::new(document);
+        let expected_digest = document.expected_digest();
+        let (result, attempts) = self.retry(&url, &target.observed_path, true, cancel, || {
+            let (response, bytes_written) = self.get_url(
+                &request,
+                target,
+                validators,
+                expected_digest.as_ref(),
+                timeouts,
+                cancel,
+            )?;
+            Ok((
+                response,
+                bytes_written,
+                read_bytes(self.keep_bytes && target.first, &path)?,
+            ))
+        });
+        let mut report = match result {
+            Ok((response, bytes_written, bytes)) => {
+                info!("downloaded {:?}", &url);
+                let mut report = DocumentReport::new(url, path, Outcome::Downloaded);
+                if response.status() == Some(StatusCode::NotModified) {
+                    report.outcome = Outcome::NotModified;
+                } else {
+                    report.bytes_written = Some(bytes_written);
+                }
+                report.status = response.status();
+                hand_over(document, target, bytes, Some(&response));
+                report.response = Some(response);
+                report
+            },
+            Err(e) => DocumentReport::failed(url, path, e),
+        };
+        report.attempts = attempts;
+        report
+    }
+
+    /// Converts `document` to `target` with `converter` (with retries) and
+    /// returns its report. The converter fetches the URL of the document
+    /// itself, unless the document was already downloaded as reported by
+    /// `source`.
+    fn convert<D>(
+        &self,
+        document: &mut D,
+        target: &Target,
+        converter: Converter,
+        source: Option<&DocumentReport>,
+        timeouts: &Timeouts,
+        cancel: &CancellationToken,
+    ) -> DocumentReport
+    where
+        D: Document + ?Sized,
+    {
+        let path = target.path.clone();
+        let url = document.url().clone();
+        let request = Request::new(document);
+        let settings = document
+            .wkhtmltopdf_settings()
+            .or(&self.wkhtmltopdf_settings);
+        let source_path = source.map(DocumentReport::path);
+        // Converting from a download doesn't send a request.
+        let requests = source.is_none();
+        let (result, attempts) = self.retry(&url, &path, requests, cancel, || {
+            let temp_file = self.temp_files.create(&path)?;
+            let command = match source {
+                Some(_) => converter.to_stdin_command(&settings, temp_file.path())?,
+                None => converter.to_command(&settings, &request, temp_file.path())?,
+            };
+            let response = match source.and_then(DocumentReport::response) {
+                Some(response) => response.clone(),
+                None => ResponseInfo::new(url.clone(), None, Headers::new()),
+            };
+            let start = Instant::now();
+            self.observer.on_conversion_started(&url, &path);
+            let result = self.get_converted(
+                temp_file.path(),
+                command,
+                response.url(),
+                source_path,
+                timeouts,
+                cancel,
+            );
+            self.observer
+                .on_conversion_finished(&url, &path, result.is_ok());
+            let bytes_written = result?;
+            let response = response.converted(start.elapsed());
+            temp_file.persist()?;
+            self.refresh_policy.store(&path, &Headers::new(), None)?;
+            Ok((
+                response,
+                bytes_written,
+                read_bytes(self.keep_bytes && target.first, &path)?,
+            ))
+        });
+        let mut report = match result {
+            Ok((response, bytes_written, bytes)) => {
+                info!("downloaded {:?}", &url);
+                let mut report = DocumentReport::new(url, path, Outcome::Converted);
+                report.bytes_written = Some(bytes_written);
+                hand_over(document, target, bytes, Some(&response));
+                report.response = Some(response);
+                report
+            },
+            Err(e) => DocumentReport::failed(url, path, e),
+        };
+        report.attempts = attempts;
+        report
+    }
+
+    fn share<D>(&self, report: &DocumentReport, document: &mut D, target: &Target) -> DocumentReport
+    where
+        D: Document + ?Sized,
+    {
+        share(
+            report,
+            document,
+            target,
+            &self.temp_files,
+            self.refresh_policy,
+            self.keep_bytes,
+        )
+    }
+
+    /// Calls `f` until it succeeds, fails with an error the retry policy does
+    /// not consider transient, or runs out of attempts. Every retry waits for
+    /// its backoff (or, if the server sent `Retry-After`, for the host to be
+    /// unpaused) and then, if `requests` is set (i.e. `f` sends a request),
+    /// for the requests per second limit. Returns the last
+    /// result along with the number of attempts made. Nothing is retried
+    /// once `cancel` is cancelled, including while waiting.
+    fn retry<T, F>(
+        &self,
+        url: &Url,
+        path: &Path,
+        requests: bool,
+        cancel: &CancellationToken,
+        mut f: F,
+    ) -> (Result<T>, usize)
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match f() {
+                Ok(value) => return (Ok(value), attempt),
+                Err(error) => error,
+            };
+            if cancel.is_cancelled() {
+                return (Err(error), attempt);
+            }
+            let backoff = self
+                .retry_policy
+                .next_backoff(&self.semaphore, url, attempt, &error);
+            match backoff {
+                Some(backoff) => {
+                    self.observer
+                        .on_retried(url, path, attempt, &error, backoff);
+                    if cancel.sleep(backoff).is_err() {
+                        return (Err(error), attempt);
+                    }
+                },
+                None => return (Err(error), attempt),
+            }
+            if requests && self.semaphore.increment_requests(url, cancel).is_err() {
+                return (Err(error), attempt);
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Sends `request` and streams the response body to `<path>.part`, where
+    /// `path` is the path of `target`, through a bounded buffer, resuming an
+    /// earlier partial download if there is one, and renames it to `path` once
+    /// it is complete. Progress is reported against the `observed_path` of
+    /// `target`. If `validators` are given, the request is conditional and a
+    /// `304 Not Modified` leaves `path` as is (only `GET` requests are
+    /// conditional or resumed). If `expected_digest` is given, the body is
+    /// hashed while it is written and discarded if its digest differs. Returns
+    /// the `ResponseInfo` of the response along with the size of the document.
+    /// The total timeout is checked between reads, so a download can exceed it
+    /// by up to the read timeout.
+    fn get_url(
+        &self,
+        request: &Request,
+        target: &Target,
+        validators: Option<&Validators>,
+        expected_digest: Option<&Digest>,
+        timeouts: &Timeouts,
+        cancel: &CancellationToken,
+    ) -> Result<(ResponseInfo, u64)> {
+        let start = Instant::now();
+        let check_total = || match timeouts.total() {
+            Some(total) if start.elapsed() > total => {
+                Err(TimeoutError::new(TimeoutKind::Total, total).into())
+            },
+            _ => Ok(()),
+        };
+        let url = &request.url;
+        let path = &target.path;
+        let client = self.inner.get(timeouts)?;
+        let part_file = PartFile::new(path);
+        let (validators, mut resume) = if request.is_get() {
+            (validators, part_file.resume())
+        } else {
+            (None, None)
+        };
+        let mut response = loop {
+            let mut builder = client.request(request.method.clone(), url.clone());
+            builder.headers(request.headers.clone());
+            if let Some(ref body) = request.body {
+                builder.body(body.clone());
+            }
+            if let Some(validators) = validators {
+                builder.headers(validators.conditional_headers());
+            }
+            if let Some(ref resume) = resume {
+                builder.headers(resume.headers());
+            }
+            let response = builder
+                .send()
+                .map_err(|e| timeout_error(e.into(), TimeoutKind::Connect, timeouts))?;
+            check_total()?;
+            if response.status() == StatusCode::RangeNotSatisfiable && resume.is_some() {
+                part_file.discard();
+                resume = None;
+                continue;
+            }
+            break response;
+        };
+        let status = response.status();
+        let info = ResponseInfo::new(
+            response.url().clone(),
+            Some(status),
+            response.headers().clone(),
         );
-        let mut process = Command::new("wkhtmltopdf")
-            .args(&arguments)
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .stdin(Stdio::null())
-            .spawn()?;
-        let exit_status = process.wait()?;
+        if status == StatusCode::NotModified && validators.is_some() {
+            debug!("{:?} not modified", url);
+            self.refresh_policy
+                .store(path, response.headers(), validators)?;
+            return Ok((info, 0));
+        }
+        let (file, offset) = part_file.open(status, response.headers(), resume.as_ref())?;
+        let content_length = response
+            .headers()
+            .get::<ContentLength>()
+            .map(|length| offset + length.0);
+        let headers = response.headers().clone();
+        let result = self.limits.check_headers(&headers, offset).and_then(|()| {
+            let part = if offset > 0 {
+                Some(part_file.path())
+            } else {
+                None
+            };
+            let mut writer = HashingWriter::new(BufWriter::new(file), expected_digest, part)?;
+            let bytes_written = cancel.copy(&mut response, &mut writer, |bytes_copied| {
+                self.observer.on_bytes_received(
+                    url,
+                    &target.observed_path,
+                    offset + bytes_copied,
+                    content_length,
+                );
+                self.limits.check_size(offset + bytes_copied)?;
+                check_total()
+            })?;
+            writer.flush()?;
+            if let (Some(expected_digest), Some(digest)) = (expected_digest, writer.finish()) {
+                expected_digest.verify(digest)?;
+            }
+            Ok(bytes_written)
+        });
+        let bytes_written = match result {
+            Ok(bytes_written) => bytes_written,
+            Err(e) => {
+                // There is no point in resuming a body that was rejected or
+                // doesn't have the expected digest.
+                if e.downcast_ref::<RejectedError>().is_some()
+                    || e.downcast_ref::<DigestMismatch>().is_some()
+                {
+                    part_file.discard();
+                }
+                return Err(timeout_error(e, TimeoutKind::Read, timeouts));
+            },
+        };
+        part_file.persist()?;
+        self.refresh_policy.store(path, &headers, None)?;
+        Ok((info, offset + bytes_written))
+    }
+
+    /// Runs `command`, a converter that converts `url` into `path`, and returns
+    /// the size of the result. If `source` is given, it holds the already
+    /// downloaded HTML, which is piped to the converter with a `<base>` tag
+    /// so that relative links still resolve against `url` (the URL it was
+    /// downloaded from, after redirects). Kills the converter
+    /// if `cancel` is cancelled or the conversion timeout expires before it
+    /// exits.
+    fn get_converted<P: AsRef<Path>>(
+        &self,
+        path: P,
+        mut command: Command,
+        url: &Url,
+        source: Option<&Path>,
+        timeouts: &Timeouts,
+        cancel: &CancellationToken,
+    ) -> Result<u64> {
+        let start = Instant::now();
+        let mut process = match source {
+            Some(source) => {
+                let mut source = File::open(source)?;
+                let mut process = command.spawn()?;
+                let mut stdin = process.stdin.take().ok_or_else(|| {
+                    format_err!("failed to open the stdin of {:?}", command.get_program())
+                })?;
+                let url = url.clone();
+                // Written from another thread so that a converter that stops
+                // reading can still be killed; its write fails once it is.
+                thread::spawn(move || {
+                    let _ = converter::write_with_base(&url, &mut source, &mut stdin);
+                });
+                process
+            },
+            None => command.spawn()?,
+        };
+        let exit_status = loop {
+            if let Some(exit_status) = process.try_wait()? {
+                break exit_status;
+            }
+            let error: Option<Error> = match timeouts.conversion() {
+                _ if cancel.is_cancelled() => Some(CancelledError.into()),
+                Some(conversion) if start.elapsed() > conversion => {
+                    Some(TimeoutError::new(TimeoutKind::Conversion, conversion).into())
+                },
+                _ => None,
+            };
+            if let Some(error) = error {
+                process.kill()?;
+                process.wait()?;
+                return Err(error);
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
         if !exit_status.success() {
-            match exit_status.code() {
-                Some(code) => bail!("process failed with exit code {}", code),
-                None => bail!("process failed with no exit code"),
+            return Err(ConversionError::new(exit_status.code()).into());
+        }
+        Ok(fs::metadata(path)?.len())
+    }
+}
+
+/// Returns the outputs of `document` as `Target`s, falling back to the
+/// default single output if `Document::outputs` returns none.
+pub(crate) fn targets<D: Document + ?Sized>(document: &D) -> Vec<Target> {
+    let mut outputs = document.outputs();
+    if outputs.is_empty() {
+        outputs.push(Output::new(document.path(), document.output_format()));
+    }
+    outputs
+        .into_iter()
+        .enumerate()
+        .map(|(index, output)| Target {
+            index,
+            path: PathBuf::from(output.path()),
+            observed_path: PathBuf::from(output.path()),
+            format: output.format(),
+            first: index == 0,
+        })
+        .collect()
+}
+
+/// Returns a key that is equal for documents that can share a fetch: those
+/// with the same request, `Document::expected_digest` and `Document::timeouts`,
+/// so that every document is verified against its own digest and fetched with
+/// its own timeouts.
+fn group_key<D>(document: &D) -> (RequestKey, Option<Digest>, Timeouts)
+where
+    D: Document + ?Sized,
+{
+    (
+        Request::new(document).key(),
+        document.expected_digest(),
+        document.timeouts(),
+    )
+}
+
+/// Returns whether any output of `document` is converted, which makes it a
+/// job for the cpu pool.
+fn is_converted<D: Document + ?Sized>(document: &D) -> bool {
+    targets(document)
+        .iter()
+        .any(|target| target.format != OutputFormat::Raw)
+}
+
+/// Hands `bytes` and `response` to `document` if `target` is its first output.
+pub(crate) fn hand_over<D>(
+    document: &mut D,
+    target: &Target,
+    bytes: Option<Vec<u8>>,
+    response: Option<&ResponseInfo>,
+) where
+    D: Document + ?Sized,
+{
+    if target.first {
+        document.set_bytes(bytes);
+        if let Some(response) = response {
+            document.set_response(response.clone());
+        }
+    }
+}
+
+/// Returns whether `target`, an output of `document`, has to be fetched. If
+/// `verify_existing_digests` is set, a raw output on disk that doesn't have the
+/// digest returned by `Document::expected_digest` is removed first, so that it
+/// is fetched again.
+pub(crate) fn freshness<D>(
+    refresh_policy: RefreshPolicy,
+    verify_existing_digests: bool,
+    document: &D,
+    target: &Target,
+) -> Freshness
+where
+    D: Document + ?Sized,
+{
+    if verify_existing_digests && target.format == OutputFormat::Raw {
+        if let Some(expected_digest) = document.expected_digest() {
+            if let Err(e) = digest::remove_mismatched(&target.path, &expected_digest) {
+                warn!("failed to verify {:?}: {}", &target.path, e);
             }
         }
-        let file = File::open(path)?;
-        let mut reader = BufReader::new(file);
-        let mut bytes = Vec::new();
-        reader.read_to_end(&mut bytes)?;
-        Ok(bytes)
+    }
+    refresh_policy.freshness(&target.path)
+}
+
+/// Returns whether the outputs of `document` that are converted have to be
+/// converted from a download rather than have the converter fetch the URL
+/// itself, so that `limits` and `Document::expected_digest` apply to the page
+/// they are converted from.
+pub(crate) fn converts_from_download<D>(limits: &Limits, document: &D) -> bool
+where
+    D: Document + ?Sized,
+{
+    !limits.is_empty() || document.expected_digest().is_some()
+}
+
+/// Returns the report of an output that was never started because its batch
+/// was cancelled.
+fn not_attempted<D: Document + ?Sized>(document: &D, target: &Target) -> DocumentReport {
+    DocumentReport::new(
+        document.url().clone(),
+        target.path.clone(),
+        Outcome::NotAttempted,
+    )
+}
+
+/// Combines the reports of the outputs of a document, along with their index
+/// in `Document::outputs`, into the report of the document: that of its first
+/// output, holding the reports of the others.
+pub(crate) fn combine(mut reports: Vec<(usize, DocumentReport)>) -> DocumentReport {
+    reports.sort_by_key(|(index, _)| *index);
+    let mut reports = reports.into_iter().map(|(_, report)| report);
+    let mut report = reports.next().expect("every output produces a report");
+    report.outputs = reports.collect();
+    report
+}
+
+/// Returns the report of `target`, an output of `document` that shares the
+/// fetch (or conversion) reported by `report`: the same failure, or a copy of
+/// the document at `report.path()` written to its own path.
+pub(crate) fn share<D>(
+    report: &DocumentReport,
+    document: &mut D,
+    target: &Target,
+    temp_files: &TempFiles,
+    refresh_policy: RefreshPolicy,
+    keep_bytes: bool,
+) -> DocumentReport
+where
+    D: Document + ?Sized,
+{
+    let path = target.path.clone();
+    let url = document.url().clone();
+    let mut shared = match report.error() {
+        Some(error) => DocumentReport::failed(url, path, duplicate_error(error)),
+        None => {
+            let result = match report.outcome() {
+                // The document on disk is as current as the one at `report.path()`.
+                Outcome::NotModified => refresh_policy.copy(report.path(), &path).map(|()| None),
+                Outcome::Converted => {
+                    copy_document(temp_files, refresh_policy, report.path(), &path, None).map(Some)
+                },
+                _ => {
+                    let expected_digest = document.expected_digest();
+                    copy_document(
+                        temp_files,
+                        refresh_policy,
+                        report.path(),
+                        &path,
+                        expected_digest.as_ref(),
+                    )
+                    .map(Some)
+                },
+            };
+            let result = result.and_then(|bytes_written| {
+                Ok((
+                    bytes_written,
+                    read_bytes(keep_bytes && target.first, &path)?,
+                ))
+            });
+            match result {
+                Ok((bytes_written, bytes)) => {
+                    hand_over(document, target, bytes, report.response());
+                    let mut shared = DocumentReport::new(url, path, report.outcome());
+                    shared.status = report.status();
+                    shared.bytes_written = bytes_written;
+                    shared.response = report.response.clone();
+                    shared
+                },
+                Err(e) => DocumentReport::failed(url, path, e),
+            }
+        },
+    };
+    shared.attempts = report.attempts();
+    shared
+}
+
+/// Copies the document at `from` to `to`, along with its validators, and
+/// returns its size. If `expected_digest` is given, the copy is only kept if it
+/// has that digest.
+fn copy_document(
+    temp_files: &TempFiles,
+    refresh_policy: RefreshPolicy,
+    from: &Path,
+    to: &Path,
+    expected_digest: Option<&Digest>,
+) -> Result<u64> {
+    let temp_file = temp_files.create(to)?;
+    let bytes_written = fs::copy(from, temp_file.path())?;
+    if let Some(expected_digest) = expected_digest {
+        expected_digest.verify(Digest::of_file(
+            expected_digest.algorithm(),
+            temp_file.path(),
+        )?)?;
+    }
+    temp_file.persist()?;
+    refresh_policy.copy(from, to)?;
+    Ok(bytes_written)
+}
+
+/// Returns an error if `status` is anything other than `200 OK`. For `429`
+/// and `503`, the error carries the delay from the `Retry-After` header.
+pub(crate) fn check_status(status: StatusCode, headers: &Headers) -> Result<()> {
+    if status == StatusCode::Ok {
+        return Ok(());
+    }
+    let retry_after = match status {
+        StatusCode::TooManyRequests | StatusCode::ServiceUnavailable => retry_after(headers),
+        _ => None,
+    };
+    Err(StatusError::new(status, retry_after).into())
+}
+
+/// Turns the timeout errors of a blocking `reqwest::Client` (`io::Error`s of
+/// kind `WouldBlock`, or `TimedOut` on Windows) that expired while waiting for
+/// `kind` into a `TimeoutError` (see `Timeouts::io`), and returns any other
+/// error as is.
+fn timeout_error(error: Error, kind: TimeoutKind, timeouts: &Timeouts) -> Error {
+    let timed_out = {
+        let io_error = error.downcast_ref::<io::Error>().or_else(|| {
+            error
+                .downcast_ref::<reqwest::Error>()
+                .and_then(|error| error.get_ref())
+                .and_then(|error| error.downcast_ref::<io::Error>())
+        });
+        matches!(
+            io_error.map(|error| error.kind()),
+            Some(io::ErrorKind::WouldBlock) | Some(io::ErrorKind::TimedOut)
+        )
+    };
+    match timeouts.io() {
+        Some(timeout) if timed_out => TimeoutError::new(timeouts.io_kind(kind), timeout).into(),
+        _ => error,
+    }
+}
+
+/// Returns the bytes written to `path` if `keep_bytes` is `true`, or `None`
+/// without touching the file if it isn't.
+pub(crate) fn read_bytes<P: AsRef<Path>>(keep_bytes: bool, path: P) -> Result<Option<Vec<u8>>> {
+    if keep_bytes {
+        read_file(path).map(Some)
+    } else {
+        Ok(None)
     }
 }
@@ -1,69 +1,243 @@
+use std::cmp;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use num_cpus;
 use reqwest;
+use reqwest::unstable::async as reqwest_async;
+use tokio_core::reactor::Handle;
 
-use client::Client;
+use async_client::AsyncClient;
+use client::{Client, ReqwestClients};
 use error::Result;
-use semaphore::Semaphore;
+use limits::Limits;
+use observer::{ClientObserver, Observer};
+use refresh::RefreshPolicy;
+use retry::RetryPolicy;
+use semaphore::{HostLimits, Semaphore};
+use temp_file::TempFiles;
+use timeouts::Timeouts;
 use wkhtmltopdf;
 
 /// A `ClientBuilder` can be used to create a `Client` with custom configuration.
 #[derive(Clone, Debug)]
 pub struct ClientBuilder {
-    max_requests_per_second: usize,
+    burst_size: usize,
+    default_host_limits: Option<HostLimits>,
+    host_limits: HashMap<String, HostLimits>,
+    keep_bytes: bool,
+    limits: Limits,
     max_threads_cpu: usize,
     max_threads_io: usize,
+    observer: Observer,
+    refresh_policy: RefreshPolicy,
     reqwest_client: Option<reqwest::Client>,
+    requests_per_second: f64,
+    retry_policy: RetryPolicy,
+    timeouts: Timeouts,
+    verify_existing_digests: bool,
     wkhtmltopdf_settings: wkhtmltopdf::Settings,
 }
 
 impl Default for ClientBuilder {
     /// Creates a `ClientBuilder` with the following default settings:
-    /// * `max_requests_per_second` = `10`
+    /// * `allowed_content_types` = empty (i.e. every content type is allowed)
+    /// * `burst_size` = `1`
+    /// * `default_host_limits` = `None` (i.e. hosts are only subject to the global limits)
+    /// * `denied_content_types` = empty
+    /// * `keep_bytes` = `true`
+    /// * `max_body_size` = `None` (i.e. no limit)
     /// * `max_threads_cpu` = number of logical cores on your machine
     /// * `max_threads_io` = `100`
-    /// * `reqwest_client` = default `reqwest::Client` plus `gzip` set to `false` and `timeout` set to `None`
+    /// * `observer` = none
+    /// * `refresh_policy` = `RefreshPolicy::Never` (i.e. documents that exist on disk are never fetched again)
+    /// * `reqwest_client` = default `reqwest::Client` plus `gzip` set to `false` and `timeout` set from `timeouts`
+    /// * `requests_per_second` = `10.0`
+    /// * `retry_policy` = `RetryPolicy::default()` (i.e. documents are only retried after a `Retry-After`)
+    /// * `timeouts` = none
+    /// * `verify_existing_digests` = `false`
     /// * `wkhtmltopdf_zoom` = `"3.5"` on macOS and `"1.0"` on any other system
     fn default() -> ClientBuilder {
         ClientBuilder {
-            max_requests_per_second: 10,
+            burst_size: 1,
+            default_host_limits: None,
+            host_limits: HashMap::new(),
+            keep_bytes: true,
+            limits: Limits::default(),
             max_threads_cpu: num_cpus::get(),
             max_threads_io: 100,
+            observer: Observer::default(),
+            refresh_policy: RefreshPolicy::default(),
             reqwest_client: None,
+            requests_per_second: 10.0,
+            retry_policy: RetryPolicy::default(),
+            timeouts: Timeouts::new(),
+            verify_existing_digests: false,
             wkhtmltopdf_settings: wkhtmltopdf::Settings::default(),
         }
     }
 }
 
 impl ClientBuilder {
-    /// Set the maximum number of requests per second.
+    /// Set the content types a response may have, as `type/subtype`,
+    /// `type/*` or `*/*` (e.g. `vec!["text/*", "application/pdf"]`). Responses
+    /// with any other content type, or none, are rejected. For converted
+    /// documents (see `OutputFormat`), this applies to the page they are
+    /// converted from.
+    pub fn set_allowed_content_types(mut self, content_types: Vec<&str>) -> ClientBuilder {
+        self.limits.allowed_content_types = content_types.into_iter().map(String::from).collect();
+        self
+    }
+
+    /// Set the maximum number of requests that can be made back to back before
+    /// the client starts spacing them out according to `requests_per_second`.
+    pub fn set_burst_size(mut self, burst_size: usize) -> ClientBuilder {
+        self.burst_size = burst_size;
+        self
+    }
+
+    /// Set the maximum number of requests per second and the maximum number of
+    /// concurrent requests for every host that doesn't have limits of its own
+    /// (see `set_host_limits`).
+    pub fn set_default_host_limits(
+        mut self,
+        requests_per_second: f64,
+        max_concurrent: usize,
+    ) -> ClientBuilder {
+        self.default_host_limits = Some(HostLimits {
+            requests_per_second,
+            max_concurrent,
+        });
+        self
+    }
+
+    /// Set the content types a response may not have, in the same format as
+    /// `set_allowed_content_types`. They take precedence over the allowed
+    /// content types.
+    pub fn set_denied_content_types(mut self, content_types: Vec<&str>) -> ClientBuilder {
+        self.limits.denied_content_types = content_types.into_iter().map(String::from).collect();
+        self
+    }
+
+    /// Set the maximum number of requests per second and the maximum number of
+    /// concurrent requests for `host` (e.g. `"www.sec.gov"`). Requests to `host`
+    /// are subject to these limits in addition to the global ones.
+    pub fn set_host_limits(
+        mut self,
+        host: &str,
+        requests_per_second: f64,
+        max_concurrent: usize,
+    ) -> ClientBuilder {
+        self.host_limits.insert(
+            host.to_lowercase(),
+            HostLimits {
+                requests_per_second,
+                max_concurrent,
+            },
+        );
+        self
+    }
+
+    /// Set whether or not documents are handed their bytes through `set_bytes`
+    /// once they are written to disk. Response bodies are always streamed to
+    /// disk; with `false`, documents receive `None` instead, so that memory use
+    /// doesn't grow with the size of the documents.
+    pub fn set_keep_bytes(mut self, keep_bytes: bool) -> ClientBuilder {
+        self.keep_bytes = keep_bytes;
+        self
+    }
+
+    /// Set the maximum size (in bytes) of a response body. Responses whose
+    /// `Content-Length` exceeds it are rejected before their body is read, and
+    /// downloads are aborted as soon as they exceed it. Rejected documents
+    /// are reported with `Outcome::Rejected`. For converted documents (see
+    /// `OutputFormat`), this applies to the page they are converted from.
+    pub fn set_max_body_size(mut self, max_body_size: u64) -> ClientBuilder {
+        self.limits.max_body_size = Some(max_body_size);
+        self
+    }
+
+    /// Set the maximum number of requests per second (shorthand for
+    /// `set_requests_per_second` with a whole number).
     pub fn set_max_requests_per_second(mut self, max_requests_per_second: usize) -> ClientBuilder {
-        self.max_requests_per_second = max_requests_per_second;
+        self.requests_per_second = max_requests_per_second as f64;
         self
     }
 
     /// Set the maximum number of cpu threads (those used for PDF conversion).
+    /// This is the size of the pool of threads a `Client` converts documents
+    /// with, and the maximum number of conversions an `AsyncClient` runs at
+    /// once. It is at least `1`; `0` is treated as `1`.
     pub fn set_max_threads_cpu(mut self, max_threads_cpu: usize) -> ClientBuilder {
-        self.max_threads_cpu = max_threads_cpu;
+        self.max_threads_cpu = cmp::max(max_threads_cpu, 1);
         self
     }
 
     /// Set the maximum number of io threads (those used for downloading bytes).
+    /// This is the size of the pool of threads a `Client` downloads documents
+    /// with, and the maximum number of documents an `AsyncClient` has in
+    /// flight. It is at least `1`; `0` is treated as `1`.
     pub fn set_max_threads_io(mut self, max_threads_io: usize) -> ClientBuilder {
-        self.max_threads_io = max_threads_io;
+        self.max_threads_io = cmp::max(max_threads_io, 1);
+        self
+    }
+
+    /// Set the maximum number of requests per second. Requests are spaced
+    /// evenly, so a fractional rate such as `0.2` means one request every five
+    /// seconds. A rate that is not a positive, finite number disables the limit.
+    pub fn set_requests_per_second(mut self, requests_per_second: f64) -> ClientBuilder {
+        self.requests_per_second = requests_per_second;
         self
     }
 
-    /// Provide your own customized `reqwest::Client`.
+    /// Register a `ClientObserver` to be notified of what the client does with
+    /// every document.
+    pub fn set_observer(mut self, observer: Arc<dyn ClientObserver>) -> ClientBuilder {
+        self.observer = Observer::new(observer);
+        self
+    }
+
+    /// Set the `RefreshPolicy` deciding whether documents that already exist on
+    /// disk are fetched again.
+    pub fn set_refresh_policy(mut self, refresh_policy: RefreshPolicy) -> ClientBuilder {
+        self.refresh_policy = refresh_policy;
+        self
+    }
+
+    /// Provide your own customized `reqwest::Client`. A `Client` then applies
+    /// its timeout instead of the connect and read timeouts of `set_timeouts`
+    /// (and of `Document::timeouts`).
     pub fn set_reqwest_client(mut self, reqwest_client: reqwest::Client) -> ClientBuilder {
         self.reqwest_client = Some(reqwest_client);
         self
     }
 
+    /// Set the `RetryPolicy` used for documents that fail for a transient reason.
+    pub fn set_retry_policy(mut self, retry_policy: RetryPolicy) -> ClientBuilder {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Set the `Timeouts` of every attempt at a document. Documents can
+    /// override some of them with `Document::timeouts`.
+    pub fn set_timeouts(mut self, timeouts: Timeouts) -> ClientBuilder {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Set whether documents that already exist on disk are hashed before
+    /// they are skipped (or revalidated). A document that doesn't have the
+    /// digest returned by `Document::expected_digest` is then deleted and
+    /// fetched again. Hashing every existing document can take a while, so
+    /// this is off by default.
+    pub fn set_verify_existing_digests(mut self, verify_existing_digests: bool) -> ClientBuilder {
+        self.verify_existing_digests = verify_existing_digests;
+        self
+    }
+
     /// Set wkhtmltopdf setting.
     pub fn set_wkhtmltopdf_setting(mut self, setting: wkhtmltopdf::Setting) -> ClientBuilder {
-        self.wkhtmltopdf_settings.set(setting);
+        self.wkhtmltopdf_settings = self.wkhtmltopdf_settings.set(setting);
         self
     }
 
@@ -73,31 +247,86 @@ impl ClientBuilder {
         settings: Vec<wkhtmltopdf::Setting>,
     ) -> ClientBuilder {
         for setting in settings {
-            self.wkhtmltopdf_settings.set(setting);
+            self.wkhtmltopdf_settings = self.wkhtmltopdf_settings.set(setting);
         }
         self
     }
 
     /// Returns a `Client` that uses this `ClientBuilder` configuration.
     pub fn build(self) -> Result<Client> {
-        let reqwest_client = match self.reqwest_client {
-            Some(reqwest_client) => reqwest_client,
-            None => {
-                reqwest::ClientBuilder::new()
-                    .gzip(false)
-                    .timeout(None)
-                    .build()?
-            },
-        };
-        let semaphore = Semaphore::new(
-            self.max_requests_per_second,
-            self.max_threads_cpu,
-            self.max_threads_io,
-        );
+        let semaphore = self.semaphore();
+        let reqwest_clients = ReqwestClients::new(self.reqwest_client);
+        // Build the client for the default timeouts now to report errors early.
+        reqwest_clients.get(&self.timeouts)?;
         Ok(Client {
+            inner: Arc::new(reqwest_clients),
+            keep_bytes: self.keep_bytes,
+            limits: self.limits,
+            max_threads_cpu: self.max_threads_cpu,
+            max_threads_io: self.max_threads_io,
+            observer: self.observer,
+            refresh_policy: self.refresh_policy,
+            retry_policy: self.retry_policy,
+            semaphore,
+            temp_files: Arc::new(TempFiles::default()),
+            timeouts: self.timeouts,
+            verify_existing_digests: self.verify_existing_digests,
+            wkhtmltopdf_settings: self.wkhtmltopdf_settings,
+        })
+    }
+
+    /// Returns an `AsyncClient` that uses this `ClientBuilder` configuration and
+    /// runs on the reactor of `handle`. A custom `reqwest::Client` provided with
+    /// `set_reqwest_client` is not used by the `AsyncClient`, which always
+    /// creates its own asynchronous client with `gzip` set to `false`.
+    pub fn build_async(self, handle: &Handle) -> Result<AsyncClient> {
+        let reqwest_client = reqwest_async::ClientBuilder::new()
+            .gzip(false)
+            .build(handle)?;
+        let semaphore = self.semaphore();
+        Ok(AsyncClient {
+            handle: handle.clone(),
             inner: reqwest_client,
-            semaphore: Arc::new(semaphore),
+            keep_bytes: self.keep_bytes,
+            limits: self.limits,
+            observer: self.observer,
+            refresh_policy: self.refresh_policy,
+            max_threads_io: self.max_threads_io,
+            retry_policy: self.retry_policy,
+            semaphore,
+            temp_files: Arc::new(TempFiles::default()),
+            timeouts: self.timeouts,
+            verify_existing_digests: self.verify_existing_digests,
             wkhtmltopdf_settings: self.wkhtmltopdf_settings,
         })
     }
+
+    fn semaphore(&self) -> Arc<Semaphore> {
+        Arc::new(Semaphore::new(
+            self.requests_per_second,
+            self.burst_size,
+            self.max_threads_cpu,
+            self.host_limits.clone(),
+            self.default_host_limits.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_threads() {
+        let builder = ClientBuilder::default()
+            .set_max_threads_cpu(0)
+            .set_max_threads_io(0);
+        assert_eq!(1, builder.max_threads_io);
+        // An `AsyncClient` takes a cpu slot for every conversion, so there has
+        // to be one (polling without a free slot would panic outside a task).
+        let semaphore = builder.semaphore();
+        assert!(semaphore
+            .poll_increment_threads_cpu(semaphore.waiter())
+            .is_some());
+    }
 }
@@ -0,0 +1,275 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use url::Url;
+
+use error::Result;
+use output_format::OutputFormat;
+use request::Request;
+use wkhtmltopdf::Settings;
+
+/// A `Converter` is the program that converts a document to its
+/// `OutputFormat`. It either sends the request of the document itself or
+/// reads the HTML that was already downloaded from its stdin.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Converter {
+    /// wkhtmltopdf, for PDFs
+    Wkhtmltopdf,
+    /// wkhtmltoimage, for images of the given `--format`
+    Wkhtmltoimage(&'static str),
+    /// pandoc, for text of the given `--to` format
+    Pandoc(&'static str),
+}
+
+impl Converter {
+    /// Returns the converter for `output_format`, or `None` if it is
+    /// `OutputFormat::Raw`.
+    pub(crate) fn new(output_format: OutputFormat) -> Option<Converter> {
+        match output_format {
+            OutputFormat::Raw => None,
+            OutputFormat::Pdf => Some(Converter::Wkhtmltopdf),
+            OutputFormat::Png => Some(Converter::Wkhtmltoimage("png")),
+            OutputFormat::Jpeg => Some(Converter::Wkhtmltoimage("jpg")),
+            OutputFormat::Text => Some(Converter::Pandoc("plain")),
+            OutputFormat::Markdown => Some(Converter::Pandoc("gfm")),
+        }
+    }
+
+    /// Returns the name of the program
+    pub(crate) fn program(self) -> &'static str {
+        match self {
+            Converter::Wkhtmltopdf => "wkhtmltopdf",
+            Converter::Wkhtmltoimage(_) => "wkhtmltoimage",
+            Converter::Pandoc(_) => "pandoc",
+        }
+    }
+
+    /// Returns a `Command` that converts the response to `request` into `path`.
+    pub(crate) fn to_command<P: AsRef<Path>>(
+        self,
+        settings: &Settings,
+        request: &Request,
+        path: P,
+    ) -> Result<Command> {
+        let mut arguments = self.arguments(settings);
+        match self {
+            Converter::Pandoc(_) => arguments.extend(request.to_pandoc_arguments()?),
+            _ => arguments.extend(request.to_wkhtmltopdf_arguments()?),
+        }
+        self.command(arguments, Some(request.url.as_str()), path)
+    }
+
+    /// Returns a `Command` that converts the HTML written to its stdin into
+    /// `path`.
+    pub(crate) fn to_stdin_command<P: AsRef<Path>>(
+        self,
+        settings: &Settings,
+        path: P,
+    ) -> Result<Command> {
+        let mut command = self.command(self.arguments(settings), None, path)?;
+        command.stdin(Stdio::piped());
+        Ok(command)
+    }
+
+    fn arguments(self, settings: &Settings) -> Vec<String> {
+        match self {
+            Converter::Wkhtmltopdf => settings.to_arguments(),
+            Converter::Wkhtmltoimage(format) => {
+                let mut arguments = vec!["--format".to_string(), format.to_string()];
+                arguments.extend(settings.to_image_arguments());
+                arguments
+            },
+            Converter::Pandoc(to) => vec![
+                "--from".to_string(),
+                "html".to_string(),
+                "--to".to_string(),
+                to.to_string(),
+            ],
+        }
+    }
+
+    /// Returns a `Command` that runs the program with `arguments` to convert
+    /// `input` (a URL, or stdin if `None`) into `path`.
+    fn command<P: AsRef<Path>>(
+        self,
+        mut arguments: Vec<String>,
+        input: Option<&str>,
+        path: P,
+    ) -> Result<Command> {
+        let path = path
+            .as_ref()
+            .to_str()
+            .ok_or_else(|| format_err!("failed to parse path: {:?}", path.as_ref()))?
+            .to_string();
+        match self {
+            // pandoc reads stdin if it is given no input.
+            Converter::Pandoc(_) => {
+                arguments.extend_from_slice(&["--output".to_string(), path]);
+                arguments.extend(input.map(String::from));
+            },
+            _ => {
+                arguments.push(input.unwrap_or("-").to_string());
+                arguments.push(path);
+            },
+        }
+        let mut command = Command::new(self.program());
+        command
+            .args(&arguments)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .stdin(Stdio::null());
+        Ok(command)
+    }
+}
+
+/// How much of an HTML document is searched for its `<head>` tag
+const HEAD_SEARCH_LIMIT: u64 = 64 * 1024;
+
+/// Copies the HTML document read from `source` to `writer`, with a `<base>`
+/// tag for `url` so that relative links still resolve against it. The tag goes
+/// right after the `<head>` tag or, if there is none, after the doctype, so
+/// that the document isn't rendered in quirks mode.
+pub(crate) fn write_with_base<R, W>(url: &Url, mut source: R, mut writer: W) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut start = Vec::new();
+    source
+        .by_ref()
+        .take(HEAD_SEARCH_LIMIT)
+        .read_to_end(&mut start)?;
+    let position = end_of_tag(&start, b"<head").or_else(|| end_of_tag(&start, b"<!doctype"));
+    let (before, after) = start.split_at(position.unwrap_or(0));
+    writer.write_all(before)?;
+    write!(writer, "<base href=\"{}\">", escape_attribute(url.as_str()))?;
+    writer.write_all(after)?;
+    io::copy(&mut source, &mut writer)?;
+    writer.flush()
+}
+
+/// Returns the position right after the first tag of `html` whose name is
+/// `name` (which includes its `<`), ignoring case.
+fn end_of_tag(html: &[u8], name: &[u8]) -> Option<usize> {
+    (0..html.len()).find_map(|start| {
+        let rest = &html[start..];
+        if rest.len() <= name.len() || !rest[..name.len()].eq_ignore_ascii_case(name) {
+            return None;
+        }
+        match rest[name.len()] {
+            b'>' | b' ' | b'\t' | b'\r' | b'\n' => {
+                let end = rest.iter().position(|&byte| byte == b'>')?;
+                Some(start + end + 1)
+            },
+            _ => None,
+        }
+    })
+}
+
+/// Escapes `value` for use in a double-quoted HTML attribute.
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use reqwest::header::Headers;
+    use reqwest::Method;
+
+    fn args(command: &Command) -> Vec<String> {
+        command
+            .get_args()
+            .map(|argument| argument.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_to_command() {
+        let settings = Settings::new();
+        let mut request = Request {
+            url: Url::parse("https://example.com/").unwrap(),
+            method: Method::Get,
+            headers: Headers::new(),
+            body: None,
+        };
+        request.headers.set_raw("Referer", "https://example.com/a");
+
+        let command = Converter::new(OutputFormat::Png)
+            .unwrap()
+            .to_command(&settings, &request, "out.png")
+            .unwrap();
+        assert_eq!("wkhtmltoimage", command.get_program());
+        let arguments = args(&command);
+        assert_eq!(["--format", "png"], arguments[..2]);
+        assert_eq!(
+            [
+                "--custom-header",
+                "Referer",
+                "https://example.com/a",
+                "https://example.com/",
+                "out.png"
+            ],
+            arguments[arguments.len() - 5..]
+        );
+
+        let command = Converter::new(OutputFormat::Markdown)
+            .unwrap()
+            .to_command(&settings, &request, "out.md")
+            .unwrap();
+        assert_eq!(
+            vec![
+                "--from",
+                "html",
+                "--to",
+                "gfm",
+                "--request-header=Referer:https://example.com/a",
+                "--output",
+                "out.md",
+                "https://example.com/",
+            ],
+            args(&command)
+        );
+        let command = Converter::Pandoc("plain")
+            .to_stdin_command(&settings, "out.txt")
+            .unwrap();
+        assert_eq!(
+            vec!["--from", "html", "--to", "plain", "--output", "out.txt"],
+            args(&command)
+        );
+
+        request.method = Method::Post;
+        request.body = Some(b"q=1".to_vec());
+        assert!(Converter::Pandoc("plain")
+            .to_command(&settings, &request, "out.txt")
+            .is_err());
+        assert_eq!(None, Converter::new(OutputFormat::Raw));
+    }
+
+    fn based(html: &str) -> String {
+        let url = Url::parse("https://example.com/a?b=1&c=\"<d>\"").unwrap();
+        let mut based = Vec::new();
+        write_with_base(&url, html.as_bytes(), &mut based).unwrap();
+        String::from_utf8(based).unwrap()
+    }
+
+    #[test]
+    fn test_write_with_base() {
+        let base = r#"<base href="https://example.com/a?b=1&amp;c=%22%3Cd%3E%22">"#;
+        assert_eq!(
+            format!("<!DOCTYPE html><html><HEAD lang=\"en\">{}<title>", base),
+            based("<!DOCTYPE html><html><HEAD lang=\"en\"><title>")
+        );
+        assert_eq!(
+            format!("<!doctype html>{}<header></header>", base),
+            based("<!doctype html><header></header>")
+        );
+        assert_eq!(format!("{}<p>", base), based("<p>"));
+    }
+}
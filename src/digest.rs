@@ -0,0 +1,227 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use sha2::{self, Sha256};
+
+use error::{DigestMismatch, Result};
+
+/// The hash algorithm of a `Digest`
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum DigestAlgorithm {
+    /// SHA-256
+    Sha256,
+}
+
+impl fmt::Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DigestAlgorithm::Sha256 => write!(f, "sha256"),
+        }
+    }
+}
+
+/// A `Digest` is a hash algorithm along with a hex encoded digest, e.g. the
+/// checksum a source publishes for a document (see `Document::expected_digest`).
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Digest {
+    algorithm: DigestAlgorithm,
+    hex: String,
+}
+
+impl Digest {
+    /// Creates a `Digest` from a hex encoded digest (in either case)
+    pub fn new(algorithm: DigestAlgorithm, hex: &str) -> Digest {
+        Digest {
+            algorithm,
+            hex: hex.trim().to_lowercase(),
+        }
+    }
+
+    /// Creates a SHA-256 `Digest` from a hex encoded digest (in either case)
+    pub fn sha256(hex: &str) -> Digest {
+        Digest::new(DigestAlgorithm::Sha256, hex)
+    }
+
+    /// Returns the hash algorithm of the digest
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the digest, hex encoded in lowercase
+    pub fn hex(&self) -> &str {
+        &self.hex
+    }
+
+    /// Returns the digest of the file at `path`.
+    pub(crate) fn of_file<P: AsRef<Path>>(algorithm: DigestAlgorithm, path: P) -> Result<Digest> {
+        let mut hasher = Hasher::new(algorithm);
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+        Ok(hasher.finish())
+    }
+
+    /// Returns a `DigestMismatch` error unless `actual` is this digest.
+    pub(crate) fn verify(&self, actual: Digest) -> Result<()> {
+        if *self == actual {
+            Ok(())
+        } else {
+            Err(DigestMismatch::new(self.clone(), actual).into())
+        }
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.hex)
+    }
+}
+
+/// Computes a `Digest` of everything written to it
+pub(crate) struct Hasher {
+    algorithm: DigestAlgorithm,
+    sha256: Sha256,
+}
+
+impl Hasher {
+    pub(crate) fn new(algorithm: DigestAlgorithm) -> Hasher {
+        Hasher {
+            algorithm,
+            sha256: Sha256::default(),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Digest {
+        let bytes = match self.algorithm {
+            DigestAlgorithm::Sha256 => sha2::Digest::result(self.sha256),
+        };
+        let hex = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        Digest {
+            algorithm: self.algorithm,
+            hex,
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.algorithm {
+            DigestAlgorithm::Sha256 => sha2::Digest::input(&mut self.sha256, buf),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A `HashingWriter` writes to `inner` and, if it has a `Hasher`, hashes what
+/// it writes, so that a body is hashed while it is written to disk.
+pub(crate) struct HashingWriter<W> {
+    inner: W,
+    hasher: Option<Hasher>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    /// Returns a `HashingWriter` computing a digest with the algorithm of
+    /// `expected`, if there is one. If the body is resumed, `part` is the part
+    /// file holding its first bytes, which are hashed first.
+    pub(crate) fn new(inner: W, expected: Option<&Digest>, part: Option<&Path>) -> Result<Self> {
+        let hasher = match expected {
+            Some(expected) => {
+                let mut hasher = Hasher::new(expected.algorithm());
+                if let Some(part) = part {
+                    io::copy(&mut File::open(part)?, &mut hasher)?;
+                }
+                Some(hasher)
+            },
+            None => None,
+        };
+        Ok(HashingWriter { inner, hasher })
+    }
+
+    /// Returns the digest of everything written, if it was hashed.
+    pub(crate) fn finish(self) -> Option<Digest> {
+        self.hasher.map(Hasher::finish)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(ref mut hasher) = self.hasher {
+            hasher.write_all(&buf[..n])?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Removes the document at `path` if it exists and doesn't have the digest
+/// `expected`, so that it is fetched again. Returns whether it was removed.
+pub(crate) fn remove_mismatched<P: AsRef<Path>>(path: P, expected: &Digest) -> Result<bool> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(false);
+    }
+    match expected.verify(Digest::of_file(expected.algorithm(), path)?) {
+        Ok(()) => Ok(false),
+        Err(e) => {
+            warn!("removing {:?}: {}", path, e);
+            fs::remove_file(path)?;
+            Ok(true)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    const HELLO: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn test_hashing_writer() {
+        let expected = Digest::sha256(&HELLO.to_uppercase());
+        let mut writer = HashingWriter::new(Vec::new(), Some(&expected), None).unwrap();
+        writer.write_all(b"hel").unwrap();
+        writer.write_all(b"lo").unwrap();
+        assert!(expected.verify(writer.finish().unwrap()).is_ok());
+
+        let mut writer = HashingWriter::new(Vec::new(), Some(&expected), None).unwrap();
+        writer.write_all(b"hello!").unwrap();
+        let error = expected.verify(writer.finish().unwrap()).unwrap_err();
+        assert_eq!(
+            &expected,
+            error.downcast_ref::<DigestMismatch>().unwrap().expected()
+        );
+    }
+
+    #[test]
+    fn test_remove_mismatched() {
+        let directory = env::temp_dir().join("urls2disk");
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("test_digest_remove_mismatched");
+
+        let part = directory.join("test_digest_remove_mismatched.part");
+        fs::write(&part, "hel").unwrap();
+        let expected = Digest::sha256(HELLO);
+        let mut writer = HashingWriter::new(Vec::new(), Some(&expected), Some(&part)).unwrap();
+        writer.write_all(b"lo").unwrap();
+        assert!(expected.verify(writer.finish().unwrap()).is_ok());
+        fs::remove_file(&part).unwrap();
+
+        fs::write(&path, "hello").unwrap();
+        assert!(!remove_mismatched(&path, &expected).unwrap());
+        fs::write(&path, "hello!").unwrap();
+        assert!(remove_mismatched(&path, &expected).unwrap());
+        assert!(!path.exists());
+        assert!(!remove_mismatched(&path, &expected).unwrap());
+    }
+}
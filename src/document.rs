@@ -1,11 +1,20 @@
 use std::path::Path;
 
+use reqwest::header::Headers;
+use reqwest::Method;
 use url::Url;
 
+use digest::Digest;
+use output::Output;
+use output_format::OutputFormat;
+use response_info::ResponseInfo;
+use timeouts::Timeouts;
+use wkhtmltopdf;
+
 /// `Document` is a trait for representing objects that can be downloaded and
-/// written to disk using the `Client` struct.  If an object implementing
-/// `Document` returns `true` from its `wkhtmltopdf()` method, it will
-/// be converted to PDF before it is written to disk.
+/// written to disk using the `Client` struct.  Unless an object implementing
+/// `Document` returns `OutputFormat::Raw` from its `output_format()` method, it
+/// will be converted (e.g. to PDF) before it is written to disk.
 pub trait Document {
     /// Returns a `&Path` representing the location on disk to write the
     /// document to
@@ -19,9 +28,146 @@ pub trait Document {
     /// converted to pdf using `wkhtmltopdf` before being written to disk.
     /// `true` means you would like the document to be converted using
     /// `wkhtmltopdf` before being written to disk. `false` means you would
-    /// like to write raw bytes only.
-    fn wkhtmltopdf(&self) -> bool;
+    /// like to write raw bytes only. Defaults to `false`.
+    #[deprecated(note = "implement `output_format` instead")]
+    fn wkhtmltopdf(&self) -> bool {
+        false
+    }
+
+    /// Returns the `OutputFormat` the document is written to disk in. Defaults
+    /// to `OutputFormat::Pdf` if `wkhtmltopdf()` returns `true` and to
+    /// `OutputFormat::Raw` otherwise, so that documents implementing only the
+    /// deprecated `wkhtmltopdf()` keep working.
+    fn output_format(&self) -> OutputFormat {
+        #[allow(deprecated)]
+        OutputFormat::from(self.wkhtmltopdf())
+    }
+
+    /// Returns the `Output`s the document is written to (e.g. the raw HTML to
+    /// `a.html`, a PDF to `a.pdf` and a thumbnail to `a.png`). They are all
+    /// produced from a single fetch: the raw body is written once and fed to
+    /// the converter of every other output. Only the first output is handed
+    /// to `set_bytes`. Defaults to a single output, `path()` in
+    /// `output_format()`; an empty `Vec` is treated the same.
+    fn outputs(&self) -> Vec<Output> {
+        vec![Output::new(self.path(), self.output_format())]
+    }
 
     /// Enables setting raw bytes of the object after they have been downloaded.
+    /// Receives `None` if the client was built with `set_keep_bytes(false)`.
     fn set_bytes(&mut self, bytes: Option<Vec<u8>>);
+
+    /// Enables setting the `ResponseInfo` of the object (e.g. its final URL
+    /// after redirects or its `Last-Modified` date) after it has been fetched
+    /// or converted. Like `set_bytes`, it is only called for the first of its
+    /// `outputs()`. For a document skipped because it was fresh on disk, it is
+    /// only called if the validators of its last download were stored (see
+    /// `RefreshPolicy`), with a `ResponseInfo` that holds nothing but them.
+    /// Defaults to ignoring it.
+    fn set_response(&mut self, _response: ResponseInfo) {}
+
+    /// Returns `Timeouts` overriding those of the client for this document
+    /// (e.g. for an endpoint that is known to be slow). Timeouts left unset
+    /// fall back to the client's. Defaults to overriding none of them.
+    fn timeouts(&self) -> Timeouts {
+        Timeouts::default()
+    }
+
+    /// Returns the priority of the document. `Client` starts documents of a
+    /// higher priority before those of a lower one; documents of the same
+    /// priority start in the order they were passed in. Defaults to `0`.
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    /// Returns the `Digest` the body of the document is expected to have (e.g.
+    /// a published SHA-256 checksum). The body is hashed while it is written
+    /// and, if its digest differs, the document fails with a `DigestMismatch`
+    /// error and the bad file is deleted. For a document converted to another
+    /// `OutputFormat`, this is the digest of the page it is converted from.
+    /// Defaults to `None`.
+    fn expected_digest(&self) -> Option<Digest> {
+        None
+    }
+
+    /// Returns the HTTP method of the request that fetches the document.
+    /// Defaults to `GET`. Only `GET` requests are made conditional (see
+    /// `RefreshPolicy`) or resumed.
+    fn method(&self) -> Method {
+        Method::Get
+    }
+
+    /// Returns headers to send along with the request that fetches the
+    /// document (e.g. `Accept` or `Referer`). wkhtmltopdf and wkhtmltoimage
+    /// send them with `--custom-header`, pandoc with `--request-header`.
+    /// Defaults to none.
+    fn headers(&self) -> Headers {
+        Headers::new()
+    }
+
+    /// Returns the body of the request that fetches the document (e.g. a JSON
+    /// query). wkhtmltopdf and wkhtmltoimage can only send a `POST` of a
+    /// form-encoded body, whose fields they send with `--post`, and pandoc
+    /// can't send a body at all. Defaults to `None`.
+    fn body(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Returns wkhtmltopdf `Settings` overriding those of the client for this
+    /// document (e.g. landscape and a smaller zoom for wide tables). Settings
+    /// left unset fall back to the client's. Only used for documents converted
+    /// with wkhtmltopdf or wkhtmltoimage. Defaults to overriding none of them.
+    fn wkhtmltopdf_settings(&self) -> wkhtmltopdf::Settings {
+        wkhtmltopdf::Settings::default()
+    }
+}
+
+/// Boxed documents are documents too, so that documents of different types
+/// can be processed in one batch as `Box<dyn Document + Send>`, whether they
+/// are passed to `Client::get_documents` as a slice or to
+/// `Client::get_documents_iter` (or `AsyncClient::get_documents`) one by one.
+impl<D: Document + ?Sized> Document for Box<D> {
+    fn path(&self) -> &Path {
+        (**self).path()
+    }
+    fn url(&self) -> &Url {
+        (**self).url()
+    }
+    #[allow(deprecated)]
+    fn wkhtmltopdf(&self) -> bool {
+        (**self).wkhtmltopdf()
+    }
+    fn output_format(&self) -> OutputFormat {
+        (**self).output_format()
+    }
+    fn outputs(&self) -> Vec<Output> {
+        (**self).outputs()
+    }
+    fn set_bytes(&mut self, bytes: Option<Vec<u8>>) {
+        (**self).set_bytes(bytes)
+    }
+    fn set_response(&mut self, response: ResponseInfo) {
+        (**self).set_response(response)
+    }
+    fn timeouts(&self) -> Timeouts {
+        (**self).timeouts()
+    }
+    fn priority(&self) -> i32 {
+        (**self).priority()
+    }
+    fn expected_digest(&self) -> Option<Digest> {
+        (**self).expected_digest()
+    }
+    fn method(&self) -> Method {
+        (**self).method()
+    }
+    fn headers(&self) -> Headers {
+        (**self).headers()
+    }
+    fn body(&self) -> Option<Vec<u8>> {
+        (**self).body()
+    }
+    fn wkhtmltopdf_settings(&self) -> wkhtmltopdf::Settings {
+        (**self).wkhtmltopdf_settings()
+    }
 }
@@ -1,7 +1,240 @@
-use failure;
+use std::fmt;
+use std::time::Duration;
+
+use failure::{self, Fail};
+use reqwest::StatusCode;
+
+use digest::Digest;
 
 /// `Error` is an alias for `failure::Error`
 pub type Error = failure::Error;
 
 /// `Result<T>` is an alias for `Result<T, Error>`
 pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// `StatusError` is the error produced when a server responds with a status
+/// other than `200 OK`. You can recover it from an `Error` with
+/// `error.downcast_ref::<StatusError>()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StatusError {
+    status: StatusCode,
+    retry_after: Option<Duration>,
+}
+
+impl StatusError {
+    pub(crate) fn new(status: StatusCode, retry_after: Option<Duration>) -> Self {
+        StatusError {
+            status,
+            retry_after,
+        }
+    }
+
+    /// Returns the `StatusCode` the server responded with
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns how long the server asked the client to wait before making
+    /// another request, if it answered `429` or `503` with a `Retry-After` header
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "response status: {}", self.status)
+    }
+}
+
+impl Fail for StatusError {}
+
+/// `ConversionError` is the error produced when a converter (e.g. wkhtmltopdf)
+/// exits unsuccessfully. You can recover it from an `Error` with
+/// `error.downcast_ref::<ConversionError>()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConversionError {
+    code: Option<i32>,
+}
+
+impl ConversionError {
+    pub(crate) fn new(code: Option<i32>) -> Self {
+        ConversionError { code }
+    }
+
+    /// Returns the exit code of the process, if it exited with one
+    pub fn code(&self) -> Option<i32> {
+        self.code
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "process failed with exit code {}", code),
+            None => write!(f, "process failed with no exit code"),
+        }
+    }
+}
+
+impl Fail for ConversionError {}
+
+/// `CancelledError` is the error of documents that were aborted in flight
+/// because their `CancellationToken` was cancelled. You can recover it from an
+/// `Error` with `error.downcast_ref::<CancelledError>()`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CancelledError;
+
+impl fmt::Display for CancelledError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl Fail for CancelledError {}
+
+/// Which of the `Timeouts` expired
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum TimeoutKind {
+    /// Connecting to the server and receiving the response headers took too long
+    Connect,
+    /// The server took too long to send the next chunk of a response body
+    Read,
+    /// The whole download took too long
+    Total,
+    /// The converter (e.g. wkhtmltopdf) took too long and was killed
+    Conversion,
+}
+
+/// `TimeoutError` is the error produced when one of the `Timeouts` of a
+/// document expires. You can recover it from an `Error` with
+/// `error.downcast_ref::<TimeoutError>()`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TimeoutError {
+    kind: TimeoutKind,
+    timeout: Duration,
+}
+
+impl TimeoutError {
+    pub(crate) fn new(kind: TimeoutKind, timeout: Duration) -> Self {
+        TimeoutError { kind, timeout }
+    }
+
+    /// Returns which timeout expired
+    pub fn kind(&self) -> TimeoutKind {
+        self.kind
+    }
+
+    /// Returns the duration of the timeout that expired
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = match self.kind {
+            TimeoutKind::Connect => "connect",
+            TimeoutKind::Read => "read",
+            TimeoutKind::Total => "download",
+            TimeoutKind::Conversion => "conversion",
+        };
+        write!(f, "{} timed out after {:?}", what, self.timeout)
+    }
+}
+
+impl Fail for TimeoutError {}
+
+/// `RejectedError` is the error of documents whose response violates the
+/// limits set with `ClientBuilder::set_max_body_size`,
+/// `ClientBuilder::set_allowed_content_types` or
+/// `ClientBuilder::set_denied_content_types`. Such documents are reported with
+/// `Outcome::Rejected`. You can recover it from an `Error` with
+/// `error.downcast_ref::<RejectedError>()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RejectedError {
+    /// The body is larger than the maximum body size (in bytes)
+    TooLarge(u64),
+    /// The `Content-Type` (or its absence, if only some types are allowed) is
+    /// not allowed
+    ContentType(Option<String>),
+}
+
+impl fmt::Display for RejectedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RejectedError::TooLarge(max_body_size) => {
+                write!(f, "body larger than {} bytes", max_body_size)
+            },
+            RejectedError::ContentType(Some(ref content_type)) => {
+                write!(f, "content type not allowed: {}", content_type)
+            },
+            RejectedError::ContentType(None) => write!(f, "missing content type"),
+        }
+    }
+}
+
+impl Fail for RejectedError {}
+
+/// `DigestMismatch` is the error of documents whose body doesn't have the
+/// digest returned by `Document::expected_digest`. The body is deleted rather
+/// than written to disk. You can recover it from an `Error` with
+/// `error.downcast_ref::<DigestMismatch>()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DigestMismatch {
+    expected: Digest,
+    actual: Digest,
+}
+
+impl DigestMismatch {
+    pub(crate) fn new(expected: Digest, actual: Digest) -> Self {
+        DigestMismatch { expected, actual }
+    }
+
+    /// Returns the digest the document was expected to have
+    pub fn expected(&self) -> &Digest {
+        &self.expected
+    }
+
+    /// Returns the digest of the body that was received
+    pub fn actual(&self) -> &Digest {
+        &self.actual
+    }
+}
+
+impl fmt::Display for DigestMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "digest mismatch: expected {}, got {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl Fail for DigestMismatch {}
+
+/// Returns a copy of `error` for every document that shares the failed fetch
+/// of a duplicate URL. `Error`s can't be cloned, so only the errors of this
+/// crate keep their type; any other error is copied as its message.
+pub(crate) fn duplicate_error(error: &Error) -> Error {
+    if let Some(error) = error.downcast_ref::<StatusError>() {
+        return error.clone().into();
+    }
+    if let Some(error) = error.downcast_ref::<ConversionError>() {
+        return error.clone().into();
+    }
+    if let Some(error) = error.downcast_ref::<CancelledError>() {
+        return (*error).into();
+    }
+    if let Some(error) = error.downcast_ref::<TimeoutError>() {
+        return (*error).into();
+    }
+    if let Some(error) = error.downcast_ref::<RejectedError>() {
+        return error.clone().into();
+    }
+    if let Some(error) = error.downcast_ref::<DigestMismatch>() {
+        return error.clone().into();
+    }
+    format_err!("{}", error)
+}
@@ -26,10 +26,19 @@
 //! install it on different setups and include them here. As far as versions go, I've only tested
 //! with wkhtmltopdf 0.12.4.
 //!
+//! Besides PDF, `urls2disk` can convert webpages to PNG or JPEG images with
+//! wkhtmltoimage (which comes with wkhtmltopdf) or to plain text or Markdown with
+//! [pandoc](https://pandoc.org/); see `OutputFormat`.
+//!
+//! A single document can also be written to several outputs, e.g. the raw HTML, a PDF
+//! and a PNG thumbnail, all produced from one fetch; see `Document::outputs`.
+//! The final URL, status, headers and fetch time of the response are handed to
+//! the document as well; see `Document::set_response`.
+//!
 //! Here's an example of downloading Apple, Inc.'s annual reports from 2010-2017
 //! from the SEC website using `urls2disk`:
 //!
-//! ```rust
+//! ```rust,no_run
 //! extern crate reqwest;
 //! extern crate urls2disk;
 //!
@@ -51,7 +60,7 @@
 //!     // Create a vector of urls we would like to download.
 //!     // These urls represent the annual reports for Apple, Inc. from 2010 to 2017.
 //!     let base = "https://www.sec.gov/Archives/edgar/data/";
-//!     let urls = vec![
+//!     let urls = [
 //!         "320193/000119312510238044/d10k.htm",
 //!         "320193/000119312511282113/d220209d10k.htm",
 //!         "320193/000119312512444068/d411355d10k.htm",
@@ -119,8 +128,14 @@
 //!     // Let the client go. It will download and write to disk all the
 //!     // documents while simultaneously respecting the 'requests per second' and
 //!     // other limits we provided. If you already have the documents on disk,
-//!     // the client will not redownload them.
-//!     client.get_documents(&mut documents)?;
+//!     // the client will not redownload them. Instead of stopping at the first
+//!     // failure, the client returns a report describing what happened to
+//!     // every document; `failed` lists those that failed, were rejected or were
+//!     // never attempted (or one of whose outputs was).
+//!     let report = client.get_documents(&mut documents)?;
+//!     for document in report.failed() {
+//!         println!("failed to download {}: {:?}", document.url(), document.error());
+//!     }
 //!
 //!     // Note: Here, if you want to, you can now access the raw bytes of all the urls
 //!     // you downloaded, since they are now stored on each SimpleDocument in addition
@@ -144,26 +159,75 @@ extern crate cfg_if;
 extern crate crossbeam;
 #[macro_use]
 extern crate failure;
+extern crate futures;
+#[cfg(unix)]
+extern crate libc;
 #[macro_use]
 extern crate log;
 extern crate num_cpus;
+extern crate rand;
 extern crate reqwest;
+extern crate sha2;
+extern crate tokio_core;
+extern crate tokio_process;
 extern crate url;
 
+mod async_client;
+mod cancellation;
 mod client;
 mod client_builder;
+mod converter;
+mod digest;
 mod document;
 mod error;
+mod limits;
+mod observer;
+mod output;
+mod output_format;
+mod part_file;
+mod rate_limiter;
+mod refresh;
+mod report;
+mod request;
+mod response_info;
+mod retry;
+mod scheduler;
 mod semaphore;
 mod simple_document;
+mod temp_file;
+mod timeouts;
 mod utils;
+mod validators;
 pub mod wkhtmltopdf;
 
-pub use client::Client;
+pub use async_client::{AsyncClient, DocumentStream};
+pub use cancellation::CancellationToken;
+pub use client::{Client, DocumentIter};
 pub use client_builder::ClientBuilder;
+pub use digest::{Digest, DigestAlgorithm};
 pub use document::Document;
-pub use error::{Error, Result};
+pub use error::{
+    CancelledError, ConversionError, DigestMismatch, Error, RejectedError, Result, StatusError,
+    TimeoutError, TimeoutKind,
+};
+pub use observer::ClientObserver;
+pub use output::Output;
+pub use output_format::OutputFormat;
+pub use refresh::RefreshPolicy;
+pub use report::{BatchReport, DocumentReport, Outcome};
+pub use response_info::ResponseInfo;
+pub use retry::{RetryErrorKind, RetryPolicy};
 pub use simple_document::SimpleDocument;
+pub use timeouts::Timeouts;
+
+/// `Headers` is an alias for `reqwest::header::Headers`
+pub type Headers = reqwest::header::Headers;
+
+/// `Method` is an alias for `reqwest::Method`
+pub type Method = reqwest::Method;
+
+/// `StatusCode` is an alias for `reqwest::StatusCode`
+pub type StatusCode = reqwest::StatusCode;
 
 /// `Url` is an alias for `url::Url`
 pub type Url = url::Url;
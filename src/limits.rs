@@ -0,0 +1,120 @@
+use reqwest::header::{ContentLength, ContentType, Headers};
+
+use error::{RejectedError, Result};
+
+/// The limits a response has to satisfy for its body to be written to disk:
+/// a maximum body size and lists of allowed and denied `Content-Type`s
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Limits {
+    pub(crate) max_body_size: Option<u64>,
+    pub(crate) allowed_content_types: Vec<String>,
+    pub(crate) denied_content_types: Vec<String>,
+}
+
+impl Limits {
+    /// Returns `true` if no limit is set
+    pub(crate) fn is_empty(&self) -> bool {
+        self.max_body_size.is_none()
+            && self.allowed_content_types.is_empty()
+            && self.denied_content_types.is_empty()
+    }
+
+    /// Checks the `Content-Type` and `Content-Length` of a response whose body
+    /// starts at `offset` (i.e. `offset` bytes were already downloaded).
+    pub(crate) fn check_headers(&self, headers: &Headers, offset: u64) -> Result<()> {
+        let content_type = headers
+            .get::<ContentType>()
+            .map(|content_type| content_type.to_string());
+        if !self.is_allowed(content_type.as_deref()) {
+            return Err(RejectedError::ContentType(content_type).into());
+        }
+        match headers.get::<ContentLength>() {
+            Some(length) => self.check_size(offset + length.0),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks the number of bytes of a body received so far.
+    pub(crate) fn check_size(&self, size: u64) -> Result<()> {
+        match self.max_body_size {
+            Some(max_body_size) if size > max_body_size => {
+                Err(RejectedError::TooLarge(max_body_size).into())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn is_allowed(&self, content_type: Option<&str>) -> bool {
+        let content_type = content_type.map(essence);
+        let matches_any = |patterns: &[String]| match content_type {
+            Some(ref content_type) => patterns
+                .iter()
+                .any(|pattern| matches(pattern, content_type)),
+            None => false,
+        };
+        if matches_any(&self.denied_content_types) {
+            return false;
+        }
+        self.allowed_content_types.is_empty() || matches_any(&self.allowed_content_types)
+    }
+}
+
+/// Returns the lowercase `type/subtype` of a `Content-Type`, without its
+/// parameters.
+fn essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase()
+}
+
+/// Returns whether `content_type` matches `pattern`, which is either a full
+/// `type/subtype`, `type/*` or `*/*`.
+fn matches(pattern: &str, content_type: &str) -> bool {
+    let pattern = essence(pattern);
+    if pattern == "*/*" || pattern == content_type {
+        return true;
+    }
+    pattern.ends_with("/*") && content_type.starts_with(&pattern[..pattern.len() - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_types() {
+        let mut limits = Limits::default();
+        assert!(limits.is_allowed(None));
+        assert!(limits.is_allowed(Some("application/zip")));
+
+        limits.allowed_content_types = vec!["text/*".to_string(), "application/pdf".to_string()];
+        limits.denied_content_types = vec!["text/csv".to_string()];
+        assert!(limits.is_allowed(Some("text/html; charset=utf-8")));
+        assert!(limits.is_allowed(Some("Application/PDF")));
+        assert!(!limits.is_allowed(Some("text/csv")));
+        assert!(!limits.is_allowed(Some("application/zip")));
+        assert!(!limits.is_allowed(Some("textual/html")));
+        assert!(!limits.is_allowed(None));
+    }
+
+    #[test]
+    fn test_size() {
+        let limits = Limits {
+            max_body_size: Some(10),
+            ..Limits::default()
+        };
+        let mut headers = Headers::new();
+        headers.set(ContentLength(8));
+        assert!(limits.check_headers(&headers, 0).is_ok());
+        let error = limits.check_headers(&headers, 4).unwrap_err();
+        assert_eq!(
+            Some(&RejectedError::TooLarge(10)),
+            error.downcast_ref::<RejectedError>()
+        );
+        assert!(limits.check_size(10).is_ok());
+        assert!(limits.check_size(11).is_err());
+    }
+}
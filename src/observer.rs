@@ -0,0 +1,119 @@
+use std::fmt;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use url::Url;
+
+use error::Error;
+use report::DocumentReport;
+
+/// A `ClientObserver` is notified of what a client does with every document,
+/// e.g. to drive a progress bar. Register one with `ClientBuilder::set_observer`.
+/// Every method does nothing by default, so implementors only have to
+/// override the events they care about. Methods are called from the client's
+/// worker threads, so they should return quickly.
+///
+/// Every document ends with exactly one call to either `on_completed` or
+/// `on_failed`.
+pub trait ClientObserver: Send + Sync {
+    /// Called for every document passed to the client. `Client::get_documents`
+    /// calls it for every document before any document is started, while
+    /// `Client::get_documents_iter` and `AsyncClient` call it as they take each
+    /// document from their input, so other documents may already have started
+    /// (or finished) by then.
+    fn on_queued(&self, _url: &Url, _path: &Path) {}
+
+    /// Called when the client starts fetching (or converting) a document
+    fn on_started(&self, _url: &Url, _path: &Path) {}
+
+    /// Called whenever a chunk of a download has been written to disk, with the
+    /// total number of bytes of the document on disk so far and, if the server
+    /// sent one, the total size of the document
+    fn on_bytes_received(
+        &self,
+        _url: &Url,
+        _path: &Path,
+        _bytes_received: u64,
+        _content_length: Option<u64>,
+    ) {
+    }
+
+    /// Called when a converter (e.g. wkhtmltopdf) is started for a document
+    fn on_conversion_started(&self, _url: &Url, _path: &Path) {}
+
+    /// Called when the converter has exited (or failed to start), with whether
+    /// or not the conversion succeeded
+    fn on_conversion_finished(&self, _url: &Url, _path: &Path, _success: bool) {}
+
+    /// Called when a document already exists on disk and is not fetched again
+    fn on_skipped_existing(&self, _url: &Url, _path: &Path) {}
+
+    /// Called when attempt number `attempt` failed with `error` and the
+    /// document will be retried after `backoff`
+    fn on_retried(
+        &self,
+        _url: &Url,
+        _path: &Path,
+        _attempt: usize,
+        _error: &Error,
+        _backoff: Duration,
+    ) {
+    }
+
+    /// Called with the report of every document for which
+    /// `DocumentReport::is_ok` returns `true` (including documents that were
+    /// skipped because they already exist)
+    fn on_completed(&self, _report: &DocumentReport) {}
+
+    /// Called with the report of every document for which
+    /// `DocumentReport::is_ok` returns `false`, i.e. that failed, was rejected
+    /// or was never attempted (or of which one of its `Document::outputs` was)
+    fn on_failed(&self, _report: &DocumentReport) {}
+}
+
+struct NoopObserver;
+
+impl ClientObserver for NoopObserver {}
+
+/// The `ClientObserver` of a client (a no-op one if the user didn't register
+/// one)
+#[derive(Clone)]
+pub(crate) struct Observer(Arc<dyn ClientObserver>);
+
+impl Observer {
+    pub(crate) fn new(observer: Arc<dyn ClientObserver>) -> Self {
+        Observer(observer)
+    }
+
+    /// Calls `on_completed` or `on_failed` depending on whether `report` (and
+    /// every report of its other outputs) is ok.
+    pub(crate) fn on_finished(&self, report: &DocumentReport) {
+        if report.is_ok() {
+            self.0.on_completed(report)
+        } else {
+            self.0.on_failed(report)
+        }
+    }
+}
+
+impl Default for Observer {
+    fn default() -> Self {
+        Observer(Arc::new(NoopObserver))
+    }
+}
+
+impl Deref for Observer {
+    type Target = dyn ClientObserver;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Observer").finish()
+    }
+}
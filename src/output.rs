@@ -0,0 +1,32 @@
+use std::path::{Path, PathBuf};
+
+use output_format::OutputFormat;
+
+/// An `Output` is one of the files a `Document` is written to (see
+/// `Document::outputs`): a path on disk along with the `OutputFormat` the
+/// document is written to it in.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Output {
+    path: PathBuf,
+    format: OutputFormat,
+}
+
+impl Output {
+    /// Creates an `Output` that writes the document to `path` in `format`
+    pub fn new<P: Into<PathBuf>>(path: P, format: OutputFormat) -> Output {
+        Output {
+            path: path.into(),
+            format,
+        }
+    }
+
+    /// Returns the `&Path` the document is written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the `OutputFormat` the document is written in
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+}
@@ -0,0 +1,30 @@
+/// The `OutputFormat` a `Document` is written to disk in. Every format but
+/// `Raw` is converted from the document by an external program, which has to
+/// be installed on your machine.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum OutputFormat {
+    /// The raw bytes of the response
+    Raw,
+    /// A PDF, converted with wkhtmltopdf
+    Pdf,
+    /// A PNG image, converted with wkhtmltoimage (which comes with wkhtmltopdf)
+    Png,
+    /// A JPEG image, converted with wkhtmltoimage (which comes with wkhtmltopdf)
+    Jpeg,
+    /// Plain text, converted with pandoc
+    Text,
+    /// GitHub-flavored Markdown, converted with pandoc
+    Markdown,
+}
+
+impl From<bool> for OutputFormat {
+    /// Maps the `bool` of the deprecated `Document::wkhtmltopdf` to `Pdf` or
+    /// `Raw`.
+    fn from(wkhtmltopdf: bool) -> OutputFormat {
+        if wkhtmltopdf {
+            OutputFormat::Pdf
+        } else {
+            OutputFormat::Raw
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use reqwest::header::{ByteRangeSpec, ContentRange, ContentRangeSpec, Headers, IfRange, Range};
+use reqwest::StatusCode;
+
+use client::check_status;
+use error::Result;
+use temp_file;
+use validators::Validators;
+
+/// A `PartFile` is `<path>.part`, the file a download is written to until it
+/// is complete. Unlike a `TempFile`, it is kept when a download fails, along
+/// with the validators of the response in `<path>.part.validators`, so that the
+/// next attempt (or the next run) can resume it with a `Range` request.
+#[derive(Clone, Debug)]
+pub(crate) struct PartFile {
+    path: PathBuf,
+    validators_path: PathBuf,
+    target: PathBuf,
+}
+
+/// Where a download resumes and which version of the resource it resumes
+#[derive(Clone, Debug)]
+pub(crate) struct Resume {
+    offset: u64,
+    if_range: IfRange,
+}
+
+impl Resume {
+    /// Returns the `Range` and `If-Range` headers of the request that resumes
+    /// the download.
+    pub(crate) fn headers(&self) -> Headers {
+        let mut headers = Headers::new();
+        headers.set(Range::Bytes(vec![ByteRangeSpec::AllFrom(self.offset)]));
+        headers.set(self.if_range.clone());
+        headers
+    }
+}
+
+impl PartFile {
+    pub(crate) fn new<P: AsRef<Path>>(target: P) -> PartFile {
+        let target = target.as_ref();
+        let with_suffix = |suffix: &str| {
+            let mut path = OsString::from(target);
+            path.push(suffix);
+            PathBuf::from(path)
+        };
+        PartFile {
+            path: with_suffix(".part"),
+            validators_path: with_suffix(".part.validators"),
+            target: target.to_path_buf(),
+        }
+    }
+
+    /// Returns the path of the part file.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns where to resume the download, if an earlier attempt left a
+    /// non-empty part file along with a validator to resume it against.
+    pub(crate) fn resume(&self) -> Option<Resume> {
+        let offset = fs::metadata(&self.path).ok()?.len();
+        if offset == 0 {
+            return None;
+        }
+        let if_range = Validators::read(&self.validators_path)?.if_range()?;
+        Some(Resume { offset, if_range })
+    }
+
+    /// Removes the part file and its validators.
+    pub(crate) fn discard(&self) {
+        for path in &[&self.path, &self.validators_path] {
+            match fs::remove_file(path) {
+                Err(ref e) if e.kind() != io::ErrorKind::NotFound => {
+                    warn!("failed to remove {:?}: {}", path, e)
+                },
+                _ => (),
+            }
+        }
+    }
+
+    /// Opens the part file to write the body of a response to the request
+    /// made with `resume`. If the server sent the requested range, the file
+    /// is opened for appending; if it sent the whole resource (because it
+    /// ignores ranges or the resource changed), the file is truncated and the
+    /// new validators are saved. Returns the file along with the offset the
+    /// body starts at.
+    pub(crate) fn open(
+        &self,
+        status: StatusCode,
+        headers: &Headers,
+        resume: Option<&Resume>,
+    ) -> Result<(File, u64)> {
+        if status == StatusCode::PartialContent {
+            if let Some(resume) = resume {
+                if range_start(headers) == Some(resume.offset) {
+                    debug!("resuming {:?} at byte {}", &self.target, resume.offset);
+                    let file = OpenOptions::new().append(true).open(&self.path)?;
+                    return Ok((file, resume.offset));
+                }
+            }
+            self.discard();
+            bail!(
+                "unexpected Content-Range: {:?}",
+                headers.get::<ContentRange>()
+            );
+        }
+        check_status(status, headers)?;
+        if resume.is_some() {
+            debug!("restarting {:?} from scratch", &self.target);
+        }
+        let file = File::create(&self.path)?;
+        match Validators::from_headers(headers) {
+            Some(validators) => validators.write(&self.validators_path)?,
+            None => {
+                if self.validators_path.exists() {
+                    fs::remove_file(&self.validators_path)?;
+                }
+            },
+        }
+        Ok((file, 0))
+    }
+
+    /// Renames the complete part file to its target and removes its
+    /// validators.
+    pub(crate) fn persist(self) -> Result<()> {
+        temp_file::persist(&self.path, &self.target)?;
+        if self.validators_path.exists() {
+            fs::remove_file(&self.validators_path)?;
+        }
+        Ok(())
+    }
+}
+
+fn range_start(headers: &Headers) -> Option<u64> {
+    match *headers.get::<ContentRange>()? {
+        ContentRange(ContentRangeSpec::Bytes {
+            range: Some((start, _)),
+            ..
+        }) => Some(start),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::io::Write;
+
+    #[test]
+    fn test_resume() {
+        let directory = env::temp_dir()
+            .join("urls2disk")
+            .join("test_part_file_resume");
+        if directory.exists() {
+            fs::remove_dir_all(&directory).unwrap();
+        }
+        fs::create_dir_all(&directory).unwrap();
+        let target = directory.join("test.bin");
+        let part_file = PartFile::new(&target);
+        assert!(part_file.resume().is_none());
+
+        let mut headers = Headers::new();
+        headers.set_raw("ETag", "\"v1\"");
+        let (mut file, offset) = part_file.open(StatusCode::Ok, &headers, None).unwrap();
+        assert_eq!(0, offset);
+        file.write_all(b"0123").unwrap();
+        drop(file);
+
+        let resume = part_file.resume().unwrap();
+        assert_eq!(4, resume.offset);
+        let request_headers = resume.headers();
+        assert_eq!(
+            Some("bytes=4-".to_string()),
+            request_headers.get::<Range>().map(|r| r.to_string())
+        );
+
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Range", "bytes 4-7/8");
+        let (mut file, offset) = part_file
+            .open(StatusCode::PartialContent, &headers, Some(&resume))
+            .unwrap();
+        assert_eq!(4, offset);
+        file.write_all(b"4567").unwrap();
+        drop(file);
+        part_file.clone().persist().unwrap();
+        assert_eq!(b"01234567".to_vec(), fs::read(&target).unwrap());
+        assert_eq!(1, fs::read_dir(&directory).unwrap().count());
+
+        let (mut file, _) = part_file
+            .open(StatusCode::Ok, &Headers::new(), None)
+            .unwrap();
+        file.write_all(b"0123").unwrap();
+        assert!(part_file.resume().is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use std::cmp;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use utils::nanos_to_duration;
+
+/// A `RateLimiter` paces requests using the generic cell rate algorithm (a
+/// token bucket that tracks a single "theoretical arrival time" instead of a
+/// token count). It lets up to `burst` requests through back to back and
+/// spaces every further request `1 / requests_per_second` apart.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    interval: Duration,
+    tolerance: Duration,
+    theoretical_arrival: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    /// Creates a `RateLimiter`. A `requests_per_second` that is not a
+    /// positive, finite number disables the limit.
+    pub(crate) fn new(requests_per_second: f64, burst: usize) -> Self {
+        let interval = if requests_per_second > 0.0 && requests_per_second.is_finite() {
+            nanos_to_duration((1_000_000_000f64 / requests_per_second) as u64)
+        } else {
+            Duration::from_secs(0)
+        };
+        let burst = cmp::max(burst, 1);
+        RateLimiter {
+            interval,
+            tolerance: interval.saturating_mul(cmp::min(burst - 1, u32::MAX as usize) as u32),
+            theoretical_arrival: Mutex::new(None),
+        }
+    }
+
+    /// Reserves the next request slot and returns how long the caller has to
+    /// wait before using it.
+    pub(crate) fn reserve(&self) -> Duration {
+        let now = Instant::now();
+        let mut theoretical_arrival = self.theoretical_arrival.lock().unwrap();
+        let arrival = cmp::max(theoretical_arrival.unwrap_or(now), now);
+        let allowed_at = arrival.checked_sub(self.tolerance).unwrap_or(now);
+        *theoretical_arrival = Some(arrival + self.interval);
+        allowed_at.saturating_duration_since(now)
+    }
+
+    /// Returns when a request would be allowed if `pending` requests were
+    /// reserved before it, without reserving anything.
+    pub(crate) fn ready_at(&self, now: Instant, pending: usize) -> Instant {
+        let theoretical_arrival = self.theoretical_arrival.lock().unwrap();
+        let pending = cmp::min(pending, u32::MAX as usize) as u32;
+        let arrival = cmp::max(theoretical_arrival.unwrap_or(now), now)
+            + self.interval.saturating_mul(pending);
+        arrival.checked_sub(self.tolerance).unwrap_or(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_about(expected: Duration, actual: Duration) {
+        let tolerance = Duration::from_millis(50);
+        assert!(
+            actual + tolerance >= expected && actual <= expected + tolerance,
+            "expected about {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_smooth_pacing() {
+        let limiter = RateLimiter::new(10.0, 1);
+        assert_about(Duration::from_millis(0), limiter.reserve());
+        assert_about(Duration::from_millis(100), limiter.reserve());
+        assert_about(Duration::from_millis(200), limiter.reserve());
+    }
+
+    #[test]
+    fn test_burst() {
+        let limiter = RateLimiter::new(2.0, 3);
+        for _ in 0..3 {
+            assert_about(Duration::from_millis(0), limiter.reserve());
+        }
+        assert_about(Duration::from_millis(500), limiter.reserve());
+        assert_about(Duration::from_millis(1000), limiter.reserve());
+    }
+
+    #[test]
+    fn test_fractional_rate() {
+        let limiter = RateLimiter::new(0.2, 1);
+        assert_about(Duration::from_millis(0), limiter.reserve());
+        assert_about(Duration::from_secs(5), limiter.reserve());
+    }
+
+    #[test]
+    fn test_ready_at() {
+        let limiter = RateLimiter::new(10.0, 1);
+        let now = Instant::now();
+        assert!(limiter.ready_at(now, 0) <= now);
+        assert_about(Duration::from_millis(200), limiter.ready_at(now, 2) - now);
+        limiter.reserve();
+        assert_about(Duration::from_millis(100), limiter.ready_at(now, 0) - now);
+        assert_about(Duration::from_millis(100), limiter.reserve());
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let limiter = RateLimiter::new(0.0, 1);
+        for _ in 0..100 {
+            assert_eq!(Duration::from_secs(0), limiter.reserve());
+        }
+    }
+}
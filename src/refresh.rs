@@ -0,0 +1,223 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use reqwest::header::Headers;
+use url::Url;
+
+use error::Result;
+use response_info::ResponseInfo;
+use validators::Validators;
+
+/// A `RefreshPolicy` decides whether a document that already exists on disk
+/// is fetched again. Unless it is `Never`, the client stores the validators
+/// (`ETag` and `Last-Modified`) of every download in `<path>.validators` and
+/// revalidates stale documents with `If-None-Match` / `If-Modified-Since`, so
+/// that unchanged documents cost a `304 Not Modified` instead of a download.
+///
+/// Converted documents (see `OutputFormat`) can't be revalidated, since their
+/// converter fetches them itself; they are converted again once stale.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum RefreshPolicy {
+    /// Never fetch a document that exists on disk again
+    Never,
+    /// Consider a document fresh for the given duration after it was last
+    /// downloaded or revalidated, and revalidate it afterwards
+    MaxAge(Duration),
+    /// Revalidate every document that exists on disk
+    Revalidate,
+}
+
+impl Default for RefreshPolicy {
+    /// Creates the `Never` policy
+    fn default() -> RefreshPolicy {
+        RefreshPolicy::Never
+    }
+}
+
+/// Whether a document has to be fetched
+#[derive(Debug, PartialEq)]
+pub(crate) enum Freshness {
+    /// The document doesn't exist on disk
+    Missing,
+    /// The document exists on disk and doesn't have to be fetched again
+    Fresh,
+    /// The document exists on disk but has to be revalidated with the
+    /// validators of its last download, if it has any
+    Stale(Option<Validators>),
+}
+
+impl RefreshPolicy {
+    pub(crate) fn freshness<P: AsRef<Path>>(&self, path: P) -> Freshness {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Freshness::Missing;
+        }
+        let validators_path = validators_path(path);
+        match *self {
+            RefreshPolicy::Never => Freshness::Fresh,
+            RefreshPolicy::MaxAge(max_age) => {
+                // The validators are rewritten on every revalidation, so their
+                // modification time is when the document was last checked.
+                let checked = fs::metadata(&validators_path)
+                    .or_else(|_| fs::metadata(path))
+                    .and_then(|metadata| metadata.modified());
+                match checked.map(|checked| checked.elapsed()) {
+                    Ok(Ok(age)) if age < max_age => Freshness::Fresh,
+                    Ok(Err(_)) => Freshness::Fresh,
+                    _ => Freshness::Stale(Validators::read(&validators_path)),
+                }
+            },
+            RefreshPolicy::Revalidate => Freshness::Stale(Validators::read(&validators_path)),
+        }
+    }
+
+    /// Stores the validators of a response for the document at `path`, keeping
+    /// those of `previous` the response doesn't repeat (a `304` only has to
+    /// repeat the `ETag`).
+    pub(crate) fn store<P: AsRef<Path>>(
+        &self,
+        path: P,
+        headers: &Headers,
+        previous: Option<&Validators>,
+    ) -> Result<()> {
+        if *self == RefreshPolicy::Never {
+            return Ok(());
+        }
+        let validators_path = validators_path(path.as_ref());
+        let validators = match (Validators::from_headers(headers), previous) {
+            (Some(validators), Some(previous)) => Some(Validators {
+                etag: validators.etag.or_else(|| previous.etag.clone()),
+                last_modified: validators.last_modified.or(previous.last_modified),
+            }),
+            (validators, previous) => validators.or_else(|| previous.cloned()),
+        };
+        match validators {
+            Some(validators) => validators.write(&validators_path),
+            None => match fs::remove_file(&validators_path) {
+                Err(ref e) if e.kind() != io::ErrorKind::NotFound => Err(format_err!(
+                    "failed to remove {:?}: {}",
+                    &validators_path,
+                    e
+                )),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    /// Removes the validators stored for the document at `path`, if any.
+    pub(crate) fn remove<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.store(path, &Headers::new(), None)
+    }
+
+    /// Returns the `ResponseInfo` of the document at `path`, fetched from `url`,
+    /// from the validators stored along with it, or `None` if there are none.
+    pub(crate) fn stored_response<P: AsRef<Path>>(
+        &self,
+        url: &Url,
+        path: P,
+    ) -> Option<ResponseInfo> {
+        let validators_path = validators_path(path.as_ref());
+        let validators = Validators::read(&validators_path)?;
+        let fetched_at = fs::metadata(&validators_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()?;
+        Some(ResponseInfo::stored(
+            url.clone(),
+            validators.to_headers(),
+            fetched_at,
+        ))
+    }
+
+    /// Stores the validators stored for the document at `from` for the
+    /// document at `to`, which is a copy of it.
+    pub(crate) fn copy<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> Result<()> {
+        let validators = Validators::read(validators_path(from.as_ref()));
+        self.store(to, &Headers::new(), validators.as_ref())
+    }
+}
+
+fn validators_path(path: &Path) -> PathBuf {
+    let mut validators_path = OsString::from(path);
+    validators_path.push(".validators");
+    PathBuf::from(validators_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs::File;
+    use std::thread;
+
+    use reqwest::header::{ETag, EntityTag};
+
+    #[test]
+    fn test_freshness() {
+        let directory = env::temp_dir()
+            .join("urls2disk")
+            .join("test_refresh_freshness");
+        if directory.exists() {
+            fs::remove_dir_all(&directory).unwrap();
+        }
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("test.html");
+        let max_age = RefreshPolicy::MaxAge(Duration::from_millis(100));
+        for policy in &[RefreshPolicy::Never, max_age, RefreshPolicy::Revalidate] {
+            assert_eq!(Freshness::Missing, policy.freshness(&path));
+        }
+
+        File::create(&path).unwrap();
+        let mut headers = Headers::new();
+        headers.set_raw("ETag", "\"v1\"");
+        max_age.store(&path, &headers, None).unwrap();
+        let validators = Validators::from_headers(&headers);
+        assert_eq!(Freshness::Fresh, RefreshPolicy::Never.freshness(&path));
+        assert_eq!(Freshness::Fresh, max_age.freshness(&path));
+        assert_eq!(
+            Freshness::Stale(validators.clone()),
+            RefreshPolicy::Revalidate.freshness(&path)
+        );
+
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(
+            Freshness::Stale(validators.clone()),
+            max_age.freshness(&path)
+        );
+        max_age
+            .store(&path, &Headers::new(), validators.as_ref())
+            .unwrap();
+        assert_eq!(Freshness::Fresh, max_age.freshness(&path));
+    }
+
+    #[test]
+    fn test_stored_response() {
+        let directory = env::temp_dir()
+            .join("urls2disk")
+            .join("test_refresh_stored_response");
+        if directory.exists() {
+            fs::remove_dir_all(&directory).unwrap();
+        }
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("test.html");
+        let url = Url::parse("https://example.com/test.html").unwrap();
+        File::create(&path).unwrap();
+        assert_eq!(None, RefreshPolicy::Revalidate.stored_response(&url, &path));
+
+        let mut headers = Headers::new();
+        headers.set_raw("ETag", "\"v1\"");
+        RefreshPolicy::Revalidate
+            .store(&path, &headers, None)
+            .unwrap();
+        let response = RefreshPolicy::Revalidate
+            .stored_response(&url, &path)
+            .unwrap();
+        assert_eq!(&url, response.url());
+        assert_eq!(None, response.status());
+        let etag = ETag(EntityTag::strong("v1".to_string()));
+        assert_eq!(Some(&etag), response.headers().get::<ETag>());
+    }
+}
@@ -0,0 +1,217 @@
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::time::Duration;
+use std::vec;
+
+use reqwest::StatusCode;
+use url::Url;
+
+use error::{Error, RejectedError, StatusError};
+use response_info::ResponseInfo;
+
+/// The `Outcome` of processing a single `Document`
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Outcome {
+    /// The document was downloaded and its raw bytes were written to disk
+    Downloaded,
+    /// The document was converted (e.g. to PDF) and written to disk
+    Converted,
+    /// The document already existed on disk, so it was not downloaded again
+    SkippedExisting,
+    /// The document already existed on disk and the server confirmed that it
+    /// hasn't changed (see `RefreshPolicy`), so it was left as is
+    NotModified,
+    /// The document could not be downloaded, converted or written to disk
+    Failed,
+    /// The response violated the maximum body size or the allowed or denied
+    /// content types, so the document was not written to disk (see
+    /// `RejectedError`)
+    Rejected,
+    /// The batch was cancelled (see `CancellationToken`) before the document
+    /// was started
+    NotAttempted,
+}
+
+/// A `DocumentReport` describes what happened to a single `Document` passed
+/// to `Client::get_documents`.
+#[derive(Debug)]
+pub struct DocumentReport {
+    pub(crate) url: Url,
+    pub(crate) path: PathBuf,
+    pub(crate) outcome: Outcome,
+    pub(crate) status: Option<StatusCode>,
+    pub(crate) bytes_written: Option<u64>,
+    pub(crate) attempts: usize,
+    pub(crate) elapsed: Duration,
+    pub(crate) error: Option<Error>,
+    pub(crate) response: Option<ResponseInfo>,
+    pub(crate) outputs: Vec<DocumentReport>,
+}
+
+impl DocumentReport {
+    pub(crate) fn new(url: Url, path: PathBuf, outcome: Outcome) -> Self {
+        DocumentReport {
+            url,
+            path,
+            outcome,
+            status: None,
+            bytes_written: None,
+            attempts: 0,
+            elapsed: Duration::from_secs(0),
+            error: None,
+            response: None,
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Returns the report of a document that failed with `error`, which is
+    /// `Outcome::Rejected` if `error` is a `RejectedError`.
+    pub(crate) fn failed(url: Url, path: PathBuf, error: Error) -> Self {
+        let outcome = match error.downcast_ref::<RejectedError>() {
+            Some(_) => Outcome::Rejected,
+            None => Outcome::Failed,
+        };
+        let mut report = DocumentReport::new(url, path, outcome);
+        report.status = error.downcast_ref::<StatusError>().map(StatusError::status);
+        report.error = Some(error);
+        report
+    }
+
+    /// Returns the `&Url` of the document
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Returns the `&Path` the document was (or would have been) written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the `Outcome` of processing the document
+    pub fn outcome(&self) -> Outcome {
+        self.outcome
+    }
+
+    /// Returns the HTTP status code of the response, if the client received one
+    pub fn status(&self) -> Option<StatusCode> {
+        self.status
+    }
+
+    /// Returns the number of bytes written to disk, if any were written
+    pub fn bytes_written(&self) -> Option<u64> {
+        self.bytes_written
+    }
+
+    /// Returns the number of attempts made to download the document (`0` if
+    /// it was never attempted, e.g. because it already existed on disk)
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+
+    /// Returns how long it took to process the document
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Returns the `Error` that caused the document to fail (or to be
+    /// rejected), if it failed
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Returns the `ResponseInfo` of the response the document was fetched
+    /// with (see `Document::set_response`), if it was fetched or converted
+    pub fn response(&self) -> Option<&ResponseInfo> {
+        self.response.as_ref()
+    }
+
+    /// Returns the reports of the outputs of the document after the first
+    /// (see `Document::outputs`), in order; this report itself describes the
+    /// first output. Empty for a document with a single output.
+    pub fn outputs(&self) -> &[DocumentReport] {
+        &self.outputs
+    }
+
+    /// Returns `true` if the document, and every one of its outputs, neither
+    /// failed, was rejected nor was left unattempted
+    pub fn is_ok(&self) -> bool {
+        !matches!(
+            self.outcome,
+            Outcome::Failed | Outcome::Rejected | Outcome::NotAttempted
+        ) && self.outputs.iter().all(DocumentReport::is_ok)
+    }
+}
+
+/// A `BatchReport` is returned by `Client::get_documents`. It contains one
+/// `DocumentReport` per document passed to the client.
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    documents: Vec<DocumentReport>,
+}
+
+impl BatchReport {
+    pub(crate) fn new(documents: Vec<DocumentReport>) -> Self {
+        BatchReport { documents }
+    }
+
+    /// Returns a slice of all the `DocumentReport`s in this batch
+    pub fn documents(&self) -> &[DocumentReport] {
+        &self.documents
+    }
+
+    /// Returns an iterator over all the `DocumentReport`s in this batch
+    pub fn iter(&self) -> slice::Iter<'_, DocumentReport> {
+        self.documents.iter()
+    }
+
+    /// Returns an iterator over the `DocumentReport`s of documents that failed,
+    /// were rejected or were left unattempted, including those of which only
+    /// one of their `Document::outputs` did (i.e. those for which
+    /// `DocumentReport::is_ok` returns `false`)
+    pub fn failed(&self) -> impl Iterator<Item = &DocumentReport> {
+        self.documents.iter().filter(|report| !report.is_ok())
+    }
+
+    /// Returns an iterator over the `DocumentReport`s with the provided
+    /// `Outcome`. Only the outcome of the first output of every document is
+    /// considered; see `DocumentReport::outputs` for the others.
+    pub fn with_outcome(&self, outcome: Outcome) -> impl Iterator<Item = &DocumentReport> {
+        self.documents
+            .iter()
+            .filter(move |report| report.outcome == outcome)
+    }
+
+    /// Returns `true` if no document in this batch failed, was rejected or was
+    /// left unattempted
+    pub fn is_ok(&self) -> bool {
+        self.documents.iter().all(DocumentReport::is_ok)
+    }
+
+    /// Returns the number of documents in this batch
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Returns `true` if this batch contains no documents
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+}
+
+impl IntoIterator for BatchReport {
+    type Item = DocumentReport;
+    type IntoIter = vec::IntoIter<DocumentReport>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.documents.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a BatchReport {
+    type Item = &'a DocumentReport;
+    type IntoIter = slice::Iter<'a, DocumentReport>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.documents.iter()
+    }
+}
@@ -0,0 +1,140 @@
+use reqwest::header::{ContentType, Headers};
+use reqwest::Method;
+use url::{form_urlencoded, Url};
+
+use document::Document;
+use error::Result;
+
+/// The key of a `Request` returned by `Request::key`
+pub(crate) type RequestKey = (Url, Method, String, Option<Vec<u8>>);
+
+/// The URL, method, headers and body of the request that fetches a document
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Request {
+    pub(crate) url: Url,
+    pub(crate) method: Method,
+    pub(crate) headers: Headers,
+    pub(crate) body: Option<Vec<u8>>,
+}
+
+impl Request {
+    pub(crate) fn new<D: Document + ?Sized>(document: &D) -> Request {
+        Request {
+            url: document.url().clone(),
+            method: document.method(),
+            headers: document.headers(),
+            body: document.body(),
+        }
+    }
+
+    /// Returns whether the request can be made conditional or resumed with a
+    /// `Range`, which only makes sense for a plain `GET`.
+    pub(crate) fn is_get(&self) -> bool {
+        self.method == Method::Get
+    }
+
+    /// Returns a key that is equal for equal requests, since `Headers` can't
+    /// be hashed.
+    pub(crate) fn key(&self) -> RequestKey {
+        (
+            self.url.clone(),
+            self.method.clone(),
+            self.headers.to_string(),
+            self.body.clone(),
+        )
+    }
+
+    /// Returns the `--custom-header` and `--post` arguments that make
+    /// wkhtmltopdf (or wkhtmltoimage, which takes the same ones) send this
+    /// request. wkhtmltopdf can only send a `GET`, or a `POST` of a form, so
+    /// any other request is an error.
+    pub(crate) fn to_wkhtmltopdf_arguments(&self) -> Result<Vec<String>> {
+        let mut arguments = Vec::new();
+        for header in self.headers.iter() {
+            arguments.push("--custom-header".to_string());
+            arguments.push(header.name().to_string());
+            arguments.push(header.value_string());
+        }
+        match (&self.method, &self.body) {
+            (Method::Get, None) => (),
+            (Method::Post, Some(body)) => {
+                let is_form = match self.headers.get::<ContentType>() {
+                    Some(content_type) => *content_type == ContentType::form_url_encoded(),
+                    None => true,
+                };
+                if !is_form {
+                    bail!("wkhtmltopdf can only POST form-encoded bodies");
+                }
+                for (name, value) in form_urlencoded::parse(body) {
+                    arguments.extend_from_slice(&["--post".to_string(), name.into(), value.into()]);
+                }
+            },
+            (method, body) => bail!(
+                "wkhtmltopdf can't send a {} request {} a body",
+                method,
+                if body.is_some() { "with" } else { "without" }
+            ),
+        }
+        Ok(arguments)
+    }
+
+    /// Returns the `--request-header` arguments that make pandoc send this
+    /// request. pandoc can only send a `GET` without a body, so any other
+    /// request is an error.
+    pub(crate) fn to_pandoc_arguments(&self) -> Result<Vec<String>> {
+        if !self.is_get() || self.body.is_some() {
+            bail!("pandoc can only send a GET request without a body");
+        }
+        Ok(self
+            .headers
+            .iter()
+            .map(|header| {
+                format!(
+                    "--request-header={}:{}",
+                    header.name(),
+                    header.value_string()
+                )
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, body: Option<&str>) -> Request {
+        Request {
+            url: Url::parse("https://example.com/search").unwrap(),
+            method,
+            headers: Headers::new(),
+            body: body.map(|body| body.as_bytes().to_vec()),
+        }
+    }
+
+    #[test]
+    fn test_to_wkhtmltopdf_arguments() {
+        let mut get = request(Method::Get, None);
+        get.headers.set_raw("Referer", "https://example.com/");
+        assert_eq!(
+            vec!["--custom-header", "Referer", "https://example.com/"],
+            get.to_wkhtmltopdf_arguments().unwrap()
+        );
+
+        let post = request(Method::Post, Some("q=rust+lang&page=2"));
+        assert_eq!(
+            vec!["--post", "q", "rust lang", "--post", "page", "2"],
+            post.to_wkhtmltopdf_arguments().unwrap()
+        );
+
+        let mut json = request(Method::Post, Some("{}"));
+        json.headers.set(ContentType::json());
+        assert!(json.to_wkhtmltopdf_arguments().is_err());
+        assert!(request(Method::Put, Some("a=b"))
+            .to_wkhtmltopdf_arguments()
+            .is_err());
+        assert!(request(Method::Post, None)
+            .to_wkhtmltopdf_arguments()
+            .is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::{Duration, SystemTime};
+
+use reqwest::header::{ContentType, Headers, LastModified};
+use reqwest::StatusCode;
+use url::Url;
+
+/// `ResponseInfo` describes the response a `Document` was fetched with and,
+/// if the document was converted, how long the conversion took. It is handed
+/// to `Document::set_response` along with the bytes of the document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResponseInfo {
+    url: Url,
+    status: Option<StatusCode>,
+    headers: Headers,
+    fetched_at: SystemTime,
+    conversion_duration: Option<Duration>,
+}
+
+impl ResponseInfo {
+    pub(crate) fn new(url: Url, status: Option<StatusCode>, headers: Headers) -> Self {
+        ResponseInfo {
+            url,
+            status,
+            headers,
+            fetched_at: SystemTime::now(),
+            conversion_duration: None,
+        }
+    }
+
+    /// Returns the info of a document skipped because it was fresh on disk,
+    /// from the `headers` stored along with it when it was last downloaded or
+    /// revalidated at `fetched_at`.
+    pub(crate) fn stored(url: Url, headers: Headers, fetched_at: SystemTime) -> Self {
+        ResponseInfo {
+            fetched_at,
+            ..ResponseInfo::new(url, None, headers)
+        }
+    }
+
+    /// Returns the info of a document converted from this response, which
+    /// took `conversion_duration`.
+    pub(crate) fn converted(mut self, conversion_duration: Duration) -> Self {
+        self.conversion_duration = Some(conversion_duration);
+        self
+    }
+
+    /// Returns the final `&Url` of the response, after redirects. For a
+    /// document the converter fetched itself (or one that was skipped because
+    /// it was fresh on disk), this is the URL of the document.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Returns the HTTP status code of the response, or `None` for a document
+    /// the converter fetched itself (or one that was skipped)
+    pub fn status(&self) -> Option<StatusCode> {
+        self.status
+    }
+
+    /// Returns the headers of the response (empty for a document the converter
+    /// fetched itself, and only the stored `ETag` and `Last-Modified` for one
+    /// that was skipped)
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Returns the `Content-Type` of the response, if it had one
+    pub fn content_type(&self) -> Option<String> {
+        self.headers
+            .get::<ContentType>()
+            .map(|content_type| content_type.to_string())
+    }
+
+    /// Returns the `Last-Modified` date of the response, if it had one
+    pub fn last_modified(&self) -> Option<SystemTime> {
+        self.headers
+            .get::<LastModified>()
+            .map(|last_modified| SystemTime::from(last_modified.0))
+    }
+
+    /// Returns when the response was received (or, for a document the
+    /// converter fetched itself, when the conversion started, and for one that
+    /// was skipped, when it was last downloaded or revalidated)
+    pub fn fetched_at(&self) -> SystemTime {
+        self.fetched_at
+    }
+
+    /// Returns how long it took to convert the document, or `None` if it
+    /// wasn't converted
+    pub fn conversion_duration(&self) -> Option<Duration> {
+        self.conversion_duration
+    }
+}
@@ -0,0 +1,368 @@
+use std::cmp;
+use std::io;
+use std::time::{Duration, SystemTime};
+
+use rand::{self, Rng};
+use reqwest::header::{Headers, RetryAfter};
+use reqwest::{self, StatusCode};
+
+use url::Url;
+
+use error::{ConversionError, Error, StatusError, TimeoutError};
+use semaphore::Semaphore;
+use utils::{duration_to_nanos, nanos_to_duration};
+
+/// A kind of error (other than an unsuccessful response status) that a
+/// `RetryPolicy` can treat as transient
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum RetryErrorKind {
+    /// The connection could not be established or was reset, aborted or
+    /// closed before the whole response was received
+    Connection,
+    /// A connect, read or write operation timed out
+    Timeout,
+    /// The converter (e.g. wkhtmltopdf) exited unsuccessfully
+    Conversion,
+}
+
+/// A `RetryPolicy` describes if, when and how often a `Client` retries a
+/// document that failed for a transient reason. Every retry goes through the
+/// same requests per second limit as the first attempt.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    base_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    retryable_statuses: Vec<StatusCode>,
+    retryable_errors: Vec<RetryErrorKind>,
+    max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Creates a `RetryPolicy` with the following default settings:
+    /// * `max_attempts` = `1` (i.e. documents are only retried once the host
+    ///   is unpaused after a `Retry-After`; see `set_max_retry_after`)
+    /// * `base_backoff` = 500 milliseconds
+    /// * `max_backoff` = 30 seconds
+    /// * `jitter` = `true`
+    /// * `retryable_statuses` = `408`, `429`, `500`, `502`, `503` and `504`
+    /// * `retryable_errors` = `Connection` and `Timeout`
+    /// * `max_retry_after` = 10 minutes
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            base_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: true,
+            retryable_statuses: vec![
+                StatusCode::RequestTimeout,
+                StatusCode::TooManyRequests,
+                StatusCode::InternalServerError,
+                StatusCode::BadGateway,
+                StatusCode::ServiceUnavailable,
+                StatusCode::GatewayTimeout,
+            ],
+            retryable_errors: vec![RetryErrorKind::Connection, RetryErrorKind::Timeout],
+            max_retry_after: Duration::from_secs(600),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Set the maximum number of attempts per document, including the first
+    /// one. `1` disables retries, except for the one after a `Retry-After`.
+    pub fn set_max_attempts(mut self, max_attempts: usize) -> RetryPolicy {
+        self.max_attempts = cmp::max(max_attempts, 1);
+        self
+    }
+
+    /// Set the delay before the first retry. The delay doubles with every
+    /// subsequent retry until it reaches `max_backoff`.
+    pub fn set_base_backoff(mut self, base_backoff: Duration) -> RetryPolicy {
+        self.base_backoff = base_backoff;
+        self
+    }
+
+    /// Set the maximum delay between two attempts.
+    pub fn set_max_backoff(mut self, max_backoff: Duration) -> RetryPolicy {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Set whether or not to randomize delays (between half and all of the
+    /// computed backoff) so that failed documents don't retry in lockstep.
+    pub fn set_jitter(mut self, jitter: bool) -> RetryPolicy {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set the response statuses that count as transient failures.
+    pub fn set_retryable_statuses(mut self, statuses: Vec<StatusCode>) -> RetryPolicy {
+        self.retryable_statuses = statuses;
+        self
+    }
+
+    /// Set the kinds of errors that count as transient failures.
+    pub fn set_retryable_errors(mut self, errors: Vec<RetryErrorKind>) -> RetryPolicy {
+        self.retryable_errors = errors;
+        self
+    }
+
+    /// Set the longest `Retry-After` the client will honor. When a server
+    /// answers `429` or `503` with a `Retry-After` header, the client pauses all
+    /// new requests to that host until the deadline (capped at this value) and
+    /// then retries the document, if its status is retryable. Such a document
+    /// is retried at least once, even if `max_attempts` is `1`. Documents whose
+    /// server asks for a longer wait are not retried.
+    pub fn set_max_retry_after(mut self, max_retry_after: Duration) -> RetryPolicy {
+        self.max_retry_after = max_retry_after;
+        self
+    }
+
+    /// Returns the delay to wait before making attempt number `attempt + 1`.
+    pub(crate) fn backoff(&self, attempt: usize) -> Duration {
+        let exponent = cmp::min(attempt.saturating_sub(1), 31) as u32;
+        let backoff = self
+            .base_backoff
+            .checked_mul(2u32.pow(exponent))
+            .map_or(self.max_backoff, |backoff| {
+                cmp::min(backoff, self.max_backoff)
+            });
+        if self.jitter {
+            let nanos = duration_to_nanos(backoff);
+            let jittered = rand::thread_rng().gen_range(nanos / 2, nanos + 1);
+            nanos_to_duration(jittered)
+        } else {
+            backoff
+        }
+    }
+
+    /// Decides what to do after attempt number `attempt` for `url` failed with
+    /// `error`. Returns `None` if the document should not be retried, or else
+    /// how long to wait before waiting for the requests per second limit and
+    /// making the next attempt. If the server sent `Retry-After` and the
+    /// document is retried, the host is paused through `semaphore` instead of
+    /// waiting here; a document that isn't retried leaves the host as is.
+    pub(crate) fn next_backoff(
+        &self,
+        semaphore: &Semaphore,
+        url: &Url,
+        attempt: usize,
+        error: &Error,
+    ) -> Option<Duration> {
+        let retry_after = error
+            .downcast_ref::<StatusError>()
+            .and_then(StatusError::retry_after);
+        // A server that says when to come back gets at least one retry.
+        let max_attempts = match retry_after {
+            Some(_) => cmp::max(self.max_attempts, 2),
+            None => self.max_attempts,
+        };
+        if attempt >= max_attempts || !self.is_retryable(error) {
+            return None;
+        }
+        let backoff = match retry_after {
+            Some(retry_after) => {
+                let pause = cmp::min(retry_after, self.max_retry_after);
+                warn!("pausing requests to {:?} for {:?}", url.host_str(), pause);
+                semaphore.pause_host(url, pause);
+                Duration::from_secs(0)
+            },
+            None => self.backoff(attempt),
+        };
+        warn!(
+            "attempt {} for {:?} failed ({}); retrying in {:?}",
+            attempt, url, error, backoff
+        );
+        Some(backoff)
+    }
+
+    pub(crate) fn is_retryable(&self, error: &Error) -> bool {
+        if let Some(error) = error.downcast_ref::<StatusError>() {
+            if let Some(retry_after) = error.retry_after() {
+                if retry_after > self.max_retry_after {
+                    return false;
+                }
+            }
+            return self.retryable_statuses.contains(&error.status());
+        }
+        let kind = if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+            if let Some(status) = error.status() {
+                return self.retryable_statuses.contains(&status);
+            }
+            if error.is_redirect() || error.is_serialization() {
+                return false;
+            }
+            match error.get_ref().and_then(|e| e.downcast_ref::<io::Error>()) {
+                Some(error) => io_error_kind(error),
+                None => Some(RetryErrorKind::Connection),
+            }
+        } else if let Some(error) = error.downcast_ref::<io::Error>() {
+            io_error_kind(error)
+        } else if error.downcast_ref::<TimeoutError>().is_some() {
+            Some(RetryErrorKind::Timeout)
+        } else if error.downcast_ref::<ConversionError>().is_some() {
+            Some(RetryErrorKind::Conversion)
+        } else {
+            None
+        };
+        match kind {
+            Some(kind) => self.retryable_errors.contains(&kind),
+            None => false,
+        }
+    }
+}
+
+/// Parses the `Retry-After` header (in either its delta-seconds or its
+/// HTTP-date form) into how long to wait from now.
+pub(crate) fn retry_after(headers: &Headers) -> Option<Duration> {
+    match *headers.get::<RetryAfter>()? {
+        RetryAfter::Delay(delay) => Some(delay),
+        RetryAfter::DateTime(date) => {
+            let date = SystemTime::from(date);
+            Some(
+                date.duration_since(SystemTime::now())
+                    .unwrap_or_else(|_| Duration::from_secs(0)),
+            )
+        },
+    }
+}
+
+fn io_error_kind(error: &io::Error) -> Option<RetryErrorKind> {
+    match error.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Some(RetryErrorKind::Timeout),
+        io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::NotConnected
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::UnexpectedEof => Some(RetryErrorKind::Connection),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use error::TimeoutKind;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::default()
+            .set_base_backoff(Duration::from_millis(100))
+            .set_max_backoff(Duration::from_millis(1000))
+            .set_jitter(false);
+        assert_eq!(Duration::from_millis(100), policy.backoff(1));
+        assert_eq!(Duration::from_millis(200), policy.backoff(2));
+        assert_eq!(Duration::from_millis(800), policy.backoff(4));
+        assert_eq!(Duration::from_millis(1000), policy.backoff(5));
+        assert_eq!(Duration::from_millis(1000), policy.backoff(100));
+    }
+
+    #[test]
+    fn test_backoff_jitter() {
+        let policy = RetryPolicy::default().set_base_backoff(Duration::from_millis(100));
+        for _ in 0..100 {
+            let backoff = policy.backoff(2);
+            assert!(backoff >= Duration::from_millis(100));
+            assert!(backoff <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let policy = RetryPolicy::default();
+        let bad_gateway = Error::from(StatusError::new(StatusCode::BadGateway, None));
+        let not_found = Error::from(StatusError::new(StatusCode::NotFound, None));
+        let reset = Error::from(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+        let denied = Error::from(io::Error::new(io::ErrorKind::PermissionDenied, "denied"));
+        let conversion = Error::from(ConversionError::new(Some(1)));
+        let timeout = Error::from(TimeoutError::new(TimeoutKind::Read, Duration::from_secs(1)));
+        assert!(policy.is_retryable(&bad_gateway));
+        assert!(policy.is_retryable(&timeout));
+        assert!(!policy.is_retryable(&not_found));
+        assert!(policy.is_retryable(&reset));
+        assert!(!policy.is_retryable(&denied));
+        assert!(!policy.is_retryable(&conversion));
+
+        let policy = policy
+            .set_retryable_statuses(vec![StatusCode::NotFound])
+            .set_retryable_errors(vec![RetryErrorKind::Conversion]);
+        assert!(!policy.is_retryable(&bad_gateway));
+        assert!(policy.is_retryable(&not_found));
+        assert!(!policy.is_retryable(&reset));
+        assert!(policy.is_retryable(&conversion));
+    }
+
+    #[test]
+    fn test_max_retry_after() {
+        let policy = RetryPolicy::default().set_max_retry_after(Duration::from_secs(60));
+        let status = StatusCode::TooManyRequests;
+        let soon = Error::from(StatusError::new(status, Some(Duration::from_secs(30))));
+        let later = Error::from(StatusError::new(status, Some(Duration::from_secs(90))));
+        assert!(policy.is_retryable(&soon));
+        assert!(!policy.is_retryable(&later));
+    }
+
+    #[test]
+    fn test_next_backoff() {
+        let policy = RetryPolicy::default();
+        let semaphore = Semaphore::default();
+        let url = Url::parse("https://www.example.com/a").unwrap();
+        let status = StatusCode::TooManyRequests;
+        let throttled = Error::from(StatusError::new(status, Some(Duration::from_secs(0))));
+        let bad_gateway = Error::from(StatusError::new(StatusCode::BadGateway, None));
+        assert_eq!(
+            Some(Duration::from_secs(0)),
+            policy.next_backoff(&semaphore, &url, 1, &throttled)
+        );
+        assert_eq!(None, policy.next_backoff(&semaphore, &url, 2, &throttled));
+        assert_eq!(None, policy.next_backoff(&semaphore, &url, 1, &bad_gateway));
+
+        let policy = policy.set_max_attempts(3);
+        assert!(policy
+            .next_backoff(&semaphore, &url, 2, &throttled)
+            .is_some());
+        assert!(policy
+            .next_backoff(&semaphore, &url, 2, &bad_gateway)
+            .is_some());
+        assert_eq!(None, policy.next_backoff(&semaphore, &url, 3, &throttled));
+    }
+
+    #[test]
+    fn test_next_backoff_pause() {
+        let policy = RetryPolicy::default().set_max_retry_after(Duration::from_secs(60));
+        let semaphore = Semaphore::default();
+        let url = Url::parse("https://www.example.com/a").unwrap();
+        let status = StatusCode::TooManyRequests;
+        let later = Error::from(StatusError::new(status, Some(Duration::from_secs(90))));
+        let soon = Error::from(StatusError::new(status, Some(Duration::from_secs(30))));
+        // A document that isn't retried doesn't pause its host.
+        assert_eq!(None, policy.next_backoff(&semaphore, &url, 1, &later));
+        assert_eq!(None, policy.next_backoff(&semaphore, &url, 2, &soon));
+        assert_eq!(None, semaphore.pause_remaining(&url));
+
+        assert!(policy.next_backoff(&semaphore, &url, 1, &soon).is_some());
+        let pause = semaphore.pause_remaining(&url).unwrap();
+        assert!(pause > Duration::from_secs(20) && pause <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = Headers::new();
+        assert_eq!(None, retry_after(&headers));
+
+        headers.set_raw("Retry-After", "120");
+        assert_eq!(Some(Duration::from_secs(120)), retry_after(&headers));
+
+        headers.set_raw("Retry-After", "Wed, 21 Oct 2015 07:28:00 GMT");
+        assert_eq!(Some(Duration::from_secs(0)), retry_after(&headers));
+
+        let date = SystemTime::now() + Duration::from_secs(300);
+        headers.set(RetryAfter::DateTime(date.into()));
+        let delay = retry_after(&headers).unwrap();
+        assert!(delay > Duration::from_secs(290) && delay <= Duration::from_secs(300));
+    }
+}
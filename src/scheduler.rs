@@ -0,0 +1,588 @@
+use std::cmp::{self, Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+use url::Url;
+
+use cancellation::{self, CancellationToken};
+use semaphore::{self, HostPermit, Semaphore};
+
+/// The pool of worker threads that processes a document
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Pool {
+    /// The threads that download documents
+    Io,
+    /// The threads that convert documents (e.g. with wkhtmltopdf)
+    Cpu,
+}
+
+/// The place of a document in its queue
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Key {
+    priority: i32,
+    sequence: usize,
+}
+
+impl Ord for Key {
+    /// Higher priorities first, then earlier entries first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .priority
+            .cmp(&self.priority)
+            .then_with(|| self.sequence.cmp(&other.sequence))
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The documents of a single host waiting for a pool, along with a URL of
+/// that host
+struct HostQueue<T> {
+    url: Url,
+    entries: BTreeMap<Key, T>,
+    /// Whether the host is thought to be ready, i.e. it wasn't found busy,
+    /// paused or rate limited since it was last released or checked again
+    ready: bool,
+}
+
+/// The documents waiting for a pool, by host. The first documents of the
+/// hosts that are ready are kept in a heap, so that the next document is found
+/// without going through every host. A host that turns out not to be ready is
+/// taken out of it until it is released or checked again.
+struct Queue<T> {
+    hosts: HashMap<String, HostQueue<T>>,
+    /// The first document of every host that is ready, along with entries that
+    /// became stale since (which are skipped once they come up)
+    ready: BinaryHeap<Reverse<(Key, String)>>,
+    /// When the hosts that aren't ready are checked again
+    waiting: BinaryHeap<Reverse<(Instant, String)>>,
+    len: usize,
+    /// The number of documents of hosts that aren't ready
+    len_waiting: usize,
+}
+
+impl<T> Queue<T> {
+    fn new() -> Self {
+        Queue {
+            hosts: HashMap::new(),
+            ready: BinaryHeap::new(),
+            waiting: BinaryHeap::new(),
+            len: 0,
+            len_waiting: 0,
+        }
+    }
+
+    /// Queues `item` for `host`, which is checked with `readiness` if it has
+    /// no other document queued (see `Semaphore::host_ready`).
+    fn insert<F>(&mut self, host: String, url: &Url, key: Key, item: T, readiness: F)
+    where
+        F: FnOnce() -> Result<(), Option<Instant>>,
+    {
+        if !self.hosts.contains_key(&host) {
+            self.hosts.insert(
+                host.clone(),
+                HostQueue {
+                    url: url.clone(),
+                    entries: BTreeMap::new(),
+                    ready: true,
+                },
+            );
+            if let Err(at) = readiness() {
+                let at = at.unwrap_or_else(|| Instant::now() + cancellation::POLL_INTERVAL);
+                self.wait(&host, at);
+            }
+        }
+        let queue = self.hosts.get_mut(&host).unwrap();
+        queue.entries.insert(key, item);
+        if !queue.ready {
+            self.len_waiting += 1;
+        } else if queue.entries.keys().next() == Some(&key) {
+            self.ready.push(Reverse((key, host)));
+        }
+        self.len += 1;
+    }
+
+    /// Returns the first document of the hosts that are ready, after those
+    /// whose time to be checked again has come are taken to be ready again.
+    fn peek(&mut self, now: Instant) -> Option<(Key, String, Url)> {
+        while let Some(&Reverse((at, _))) = self.waiting.peek() {
+            if at > now {
+                break;
+            }
+            let Reverse((_, host)) = self.waiting.pop().unwrap();
+            self.release(&host);
+        }
+        while let Some(Reverse((key, host))) = self.ready.peek() {
+            if let Some(queue) = self.hosts.get(host) {
+                if queue.ready && queue.entries.keys().next() == Some(key) {
+                    return Some((*key, host.clone(), queue.url.clone()));
+                }
+            }
+            self.ready.pop();
+        }
+        None
+    }
+
+    /// Returns the first document of every host, whether or not it is ready.
+    fn first(&self) -> Option<(Key, String, Url)> {
+        self.hosts
+            .iter()
+            .filter_map(|(host, queue)| {
+                let key = queue.entries.keys().next()?;
+                Some((*key, host.clone(), queue.url.clone()))
+            })
+            .min_by_key(|(key, _, _)| *key)
+    }
+
+    /// Takes `host` out of the hosts that are ready until it is released or
+    /// `at`, when it is checked again.
+    fn wait(&mut self, host: &str, at: Instant) {
+        if let Some(queue) = self.hosts.get_mut(host) {
+            if queue.ready {
+                queue.ready = false;
+                self.len_waiting += queue.entries.len();
+            }
+            self.waiting.push(Reverse((at, host.to_string())));
+        }
+    }
+
+    /// Takes `host` to be ready again.
+    fn release(&mut self, host: &str) {
+        if let Some(queue) = self.hosts.get_mut(host) {
+            if !queue.ready {
+                queue.ready = true;
+                self.len_waiting -= queue.entries.len();
+                if let Some(key) = queue.entries.keys().next() {
+                    self.ready.push(Reverse((*key, host.to_string())));
+                }
+            }
+        }
+    }
+
+    /// Returns when the first of the hosts that aren't ready is checked again.
+    fn next_check(&self) -> Option<Instant> {
+        self.waiting.peek().map(|&Reverse((at, _))| at)
+    }
+
+    fn remove(&mut self, host: &str, key: Key) -> Option<T> {
+        let (item, empty) = {
+            let queue = self.hosts.get_mut(host)?;
+            let item = queue.entries.remove(&key)?;
+            if !queue.ready {
+                self.len_waiting -= 1;
+            } else if let Some(next) = queue.entries.keys().next() {
+                if *next > key {
+                    self.ready.push(Reverse((*next, host.to_string())));
+                }
+            }
+            (item, queue.entries.is_empty())
+        };
+        if empty {
+            self.hosts.remove(host);
+        }
+        self.len -= 1;
+        Some(item)
+    }
+
+    fn clear(&mut self) {
+        self.hosts.clear();
+        self.ready.clear();
+        self.waiting.clear();
+        self.len = 0;
+        self.len_waiting = 0;
+    }
+}
+
+struct State<T> {
+    cpu: Queue<T>,
+    io: Queue<T>,
+    /// The number of workers of each pool waiting in `pop`
+    idle_cpu: usize,
+    idle_io: usize,
+    sequence: usize,
+    closed: bool,
+    aborted: bool,
+}
+
+impl<T> State<T> {
+    fn queue(&mut self, pool: Pool) -> &mut Queue<T> {
+        match pool {
+            Pool::Cpu => &mut self.cpu,
+            Pool::Io => &mut self.io,
+        }
+    }
+
+    fn idle(&mut self, pool: Pool) -> &mut usize {
+        match pool {
+            Pool::Cpu => &mut self.idle_cpu,
+            Pool::Io => &mut self.idle_io,
+        }
+    }
+}
+
+/// A `Scheduler` hands documents to the worker pools by priority. A pool only
+/// starts a document once no document of a higher priority that may start is
+/// waiting for it, or for the other pool while that pool has an idle worker to
+/// start it. A pool whose workers are all busy doesn't hold up the other one,
+/// so both pools stay busy. Documents of the same priority start in the order
+/// they were pushed.
+///
+/// A document may only start once its host has a free concurrent request
+/// slot, isn't paused and its requests per second limit allows another
+/// request. Documents whose host isn't ready are passed over for those of
+/// other hosts, so that a slow host doesn't tie up the workers of every other
+/// host.
+pub(crate) struct Scheduler<T> {
+    state: Mutex<State<T>>,
+    condvar: Condvar,
+    semaphore: Arc<Semaphore>,
+    capacity_cpu: usize,
+    capacity_io: usize,
+}
+
+impl<T> Scheduler<T> {
+    /// Returns a `Scheduler` whose queues can hold any number of documents.
+    pub(crate) fn unbounded(semaphore: Arc<Semaphore>) -> Self {
+        Scheduler::bounded(semaphore, usize::MAX, usize::MAX)
+    }
+
+    /// Returns a `Scheduler` whose queues hold `capacity_cpu` and `capacity_io`
+    /// documents, and up to as many again as long as that many documents of
+    /// hosts that aren't ready are queued, so that documents of other hosts can
+    /// still be queued while a host isn't ready.
+    pub(crate) fn bounded(
+        semaphore: Arc<Semaphore>,
+        capacity_cpu: usize,
+        capacity_io: usize,
+    ) -> Self {
+        Scheduler {
+            state: Mutex::new(State {
+                cpu: Queue::new(),
+                io: Queue::new(),
+                idle_cpu: 0,
+                idle_io: 0,
+                sequence: 0,
+                closed: false,
+                aborted: false,
+            }),
+            condvar: Condvar::new(),
+            semaphore,
+            capacity_cpu,
+            capacity_io,
+        }
+    }
+
+    /// Queues `item`, a document for `url`, for `pool`, blocking while the
+    /// queue of `pool` is full. Gives `item` back if the scheduler was aborted.
+    pub(crate) fn push(&self, pool: Pool, priority: i32, url: &Url, item: T) -> Result<(), T> {
+        let host = semaphore::host(url);
+        let mut state = self.state.lock().unwrap();
+        while !state.aborted && self.is_full(state.queue(pool), pool) {
+            state = self.condvar.wait(state).unwrap();
+        }
+        if state.aborted {
+            return Err(item);
+        }
+        let key = Key {
+            priority,
+            sequence: state.sequence,
+        };
+        state.sequence += 1;
+        let semaphore = &self.semaphore;
+        state
+            .queue(pool)
+            .insert(host, url, key, item, || semaphore.host_ready(url));
+        self.condvar.notify_all();
+        Ok(())
+    }
+
+    /// Takes the next document for `pool`, blocking until there is one that
+    /// may start, and returns it along with the `HostPermit` of its host.
+    /// Returns `None` once the scheduler is closed and the queue of `pool` is
+    /// empty, or once it is aborted.
+    ///
+    /// Once `cancel` is cancelled, the documents are handed out right away
+    /// whether or not their host is ready, so that they can be reported as not
+    /// attempted.
+    pub(crate) fn pop(&self, pool: Pool, cancel: &CancellationToken) -> Option<(T, HostPermit)> {
+        let other = match pool {
+            Pool::Cpu => Pool::Io,
+            Pool::Io => Pool::Cpu,
+        };
+        let mut state = self.state.lock().unwrap();
+        *state.idle(pool) += 1;
+        let popped = loop {
+            if state.aborted {
+                break None;
+            }
+            if cancel.is_cancelled() {
+                if let Some((key, host, url)) = state.queue(pool).first() {
+                    let item = state.queue(pool).remove(&host, key);
+                    self.condvar.notify_all();
+                    let permit = self.semaphore.force_acquire_host(&url);
+                    break item.map(|item| (item, permit));
+                }
+            }
+            let now = Instant::now();
+            // `None` (nothing that may start waiting for the other pool, or no
+            // idle worker of the other pool to start it) is less than any
+            // priority.
+            let waiting = if *state.idle(other) > 0 {
+                state.queue(other).peek(now).map(|(key, _, _)| key.priority)
+            } else {
+                None
+            };
+            let mut passed_over = false;
+            let mut started = None;
+            while let Some((key, host, url)) = state.queue(pool).peek(now) {
+                if waiting > Some(key.priority) {
+                    break;
+                }
+                match self.semaphore.try_acquire_host(&url) {
+                    Ok(permit) => {
+                        started = Some((key, host, permit));
+                        break;
+                    },
+                    // A host that is busy is released once a document of it
+                    // is done, but is checked again meanwhile in case its
+                    // slot is released outside of this scheduler.
+                    Err(at) => {
+                        let at = at.unwrap_or(now + cancellation::POLL_INTERVAL);
+                        state.queue(pool).wait(&host, at);
+                        passed_over = true;
+                    },
+                }
+            }
+            if passed_over {
+                // The other pool may have been held up by a document that
+                // turned out not to be ready.
+                self.condvar.notify_all();
+            }
+            if let Some((key, host, permit)) = started {
+                let item = state.queue(pool).remove(&host, key);
+                self.condvar.notify_all();
+                break item.map(|item| (item, permit));
+            }
+            if state.closed && state.queue(pool).len == 0 {
+                break None;
+            }
+            // Nothing wakes the workers when `cancel` is cancelled.
+            let timeout =
+                state
+                    .queue(pool)
+                    .next_check()
+                    .map_or(cancellation::POLL_INTERVAL, |at| {
+                        cmp::min(
+                            at.saturating_duration_since(Instant::now()),
+                            cancellation::POLL_INTERVAL,
+                        )
+                    });
+            state = self.condvar.wait_timeout(state, timeout).unwrap().0;
+        };
+        *state.idle(pool) -= 1;
+        popped
+    }
+
+    /// Returns whether `queue`, the queue of `pool`, can't take another
+    /// document (see `bounded`).
+    fn is_full(&self, queue: &Queue<T>, pool: Pool) -> bool {
+        let capacity = match pool {
+            Pool::Cpu => self.capacity_cpu,
+            Pool::Io => self.capacity_io,
+        };
+        queue.len >= capacity.saturating_add(cmp::min(queue.len_waiting, capacity))
+    }
+
+    /// Takes the host of `url` to be ready again once a document for `url` has
+    /// released its `HostPermit`, and wakes the workers waiting for a document.
+    pub(crate) fn release(&self, url: &Url) {
+        let host = semaphore::host(url);
+        let mut state = self.state.lock().unwrap();
+        state.cpu.release(&host);
+        state.io.release(&host);
+        self.condvar.notify_all();
+    }
+
+    /// Marks the end of the input: once their queue is empty, `pop` returns
+    /// `None` instead of waiting for more documents.
+    pub(crate) fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.condvar.notify_all();
+    }
+
+    /// Drops every queued document and makes `push` and `pop` fail from now on.
+    pub(crate) fn abort(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.aborted = true;
+        state.cpu.clear();
+        state.io.clear();
+        self.condvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use semaphore::HostLimits;
+
+    fn url(host: &str) -> Url {
+        Url::parse(&format!("https://{}/", host)).unwrap()
+    }
+
+    fn pop<T>(scheduler: &Scheduler<T>, pool: Pool) -> Option<T> {
+        scheduler
+            .pop(pool, &CancellationToken::new())
+            .map(|(item, _)| item)
+    }
+
+    #[test]
+    fn test_priorities() {
+        let scheduler = Scheduler::unbounded(Arc::new(Semaphore::default()));
+        let url = url("www.example.com");
+        for &(pool, priority, item) in &[
+            (Pool::Io, 0, "a"),
+            (Pool::Cpu, 0, "b"),
+            (Pool::Io, 1, "c"),
+            (Pool::Io, 0, "d"),
+            (Pool::Cpu, 1, "e"),
+        ] {
+            assert!(scheduler.push(pool, priority, &url, item).is_ok());
+        }
+        scheduler.close();
+        assert_eq!(Some("c"), pop(&scheduler, Pool::Io));
+        assert_eq!(Some("e"), pop(&scheduler, Pool::Cpu));
+        assert_eq!(Some("a"), pop(&scheduler, Pool::Io));
+        assert_eq!(Some("d"), pop(&scheduler, Pool::Io));
+        assert_eq!(None, pop(&scheduler, Pool::Io));
+        assert_eq!(Some("b"), pop(&scheduler, Pool::Cpu));
+        assert_eq!(None, pop(&scheduler, Pool::Cpu));
+    }
+
+    #[test]
+    fn test_busy_pool() {
+        let scheduler = Scheduler::unbounded(Arc::new(Semaphore::default()));
+        let url = url("www.example.com");
+        for &(pool, priority, item) in
+            &[(Pool::Cpu, 1, "a"), (Pool::Cpu, 1, "b"), (Pool::Io, 0, "c")]
+        {
+            assert!(scheduler.push(pool, priority, &url, item).is_ok());
+        }
+        scheduler.close();
+        assert_eq!(Some("a"), pop(&scheduler, Pool::Cpu));
+        // The cpu worker is busy with "a", so "b" doesn't hold up the io pool.
+        assert_eq!(Some("c"), pop(&scheduler, Pool::Io));
+        assert_eq!(None, pop(&scheduler, Pool::Io));
+        assert_eq!(Some("b"), pop(&scheduler, Pool::Cpu));
+    }
+
+    fn semaphore() -> Arc<Semaphore> {
+        let mut host_limits = HashMap::new();
+        host_limits.insert(
+            "slow.example.com".to_string(),
+            HostLimits {
+                requests_per_second: 0.0,
+                max_concurrent: 1,
+            },
+        );
+        Arc::new(Semaphore::new(0.0, 1, 1, host_limits, None))
+    }
+
+    #[test]
+    fn test_busy_host() {
+        let scheduler = Scheduler::unbounded(semaphore());
+        let (slow, fast) = (url("slow.example.com"), url("fast.example.com"));
+        for &(priority, url, item) in &[(1, &slow, "a"), (1, &slow, "b"), (0, &fast, "c")] {
+            assert!(scheduler.push(Pool::Io, priority, url, item).is_ok());
+        }
+        scheduler.close();
+        let (a, permit) = scheduler.pop(Pool::Io, &CancellationToken::new()).unwrap();
+        assert_eq!("a", a);
+        // "b" has to wait for "a" to release the slot of its host.
+        assert_eq!(Some("c"), pop(&scheduler, Pool::Io));
+        drop(permit);
+        scheduler.release(&slow);
+        assert_eq!(Some("b"), pop(&scheduler, Pool::Io));
+        assert_eq!(None, pop(&scheduler, Pool::Io));
+    }
+
+    #[test]
+    fn test_bounded_busy_host() {
+        let scheduler = Scheduler::bounded(semaphore(), 1, 1);
+        let (slow, fast) = (url("slow.example.com"), url("fast.example.com"));
+        assert!(scheduler.push(Pool::Io, 0, &slow, "a").is_ok());
+        let (_, permit) = scheduler.pop(Pool::Io, &CancellationToken::new()).unwrap();
+        assert!(scheduler.push(Pool::Io, 0, &slow, "b").is_ok());
+        // "b" waits for its host, so it leaves room for a document of another host.
+        assert!(scheduler.push(Pool::Io, 0, &fast, "c").is_ok());
+        assert_eq!(Some("c"), pop(&scheduler, Pool::Io));
+        drop(permit);
+        scheduler.release(&slow);
+        assert_eq!(Some("b"), pop(&scheduler, Pool::Io));
+    }
+
+    #[test]
+    fn test_bounded_many_hosts() {
+        let semaphore = Arc::new(Semaphore::new(
+            0.0,
+            1,
+            1,
+            HashMap::new(),
+            Some(HostLimits {
+                requests_per_second: 0.0,
+                max_concurrent: 1,
+            }),
+        ));
+        let scheduler = Scheduler::bounded(semaphore.clone(), 2, 2);
+        let urls = (0..6)
+            .map(|i| url(&format!("host{}.example.com", i)))
+            .collect::<Vec<_>>();
+        // Every host is busy, so none of their documents may start.
+        let _permits = urls
+            .iter()
+            .map(|url| semaphore.try_acquire_host(url).unwrap())
+            .collect::<Vec<_>>();
+        for (i, url) in urls.iter().take(4).enumerate() {
+            assert!(!scheduler.is_full(&scheduler.state.lock().unwrap().io, Pool::Io));
+            assert!(scheduler.push(Pool::Io, 0, url, i).is_ok());
+        }
+        // More hosts than `capacity` don't make room for more documents.
+        let state = scheduler.state.lock().unwrap();
+        assert!(scheduler.is_full(&state.io, Pool::Io));
+        assert_eq!(4, state.io.len);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let scheduler = Scheduler::unbounded(semaphore());
+        let slow = url("slow.example.com");
+        for &item in &["a", "b"] {
+            assert!(scheduler.push(Pool::Io, 0, &slow, item).is_ok());
+        }
+        scheduler.close();
+        let cancel = CancellationToken::new();
+        let (_, _permit) = scheduler.pop(Pool::Io, &cancel).unwrap();
+        // "b" is handed out even though its host is busy.
+        cancel.cancel();
+        assert_eq!(
+            Some("b"),
+            scheduler.pop(Pool::Io, &cancel).map(|(item, _)| item)
+        );
+        assert!(scheduler.pop(Pool::Io, &cancel).is_none());
+    }
+
+    #[test]
+    fn test_abort() {
+        let scheduler = Scheduler::bounded(Arc::new(Semaphore::default()), 1, 1);
+        let url = url("www.example.com");
+        assert!(scheduler.push(Pool::Io, 0, &url, "a").is_ok());
+        scheduler.abort();
+        assert_eq!(Err("b"), scheduler.push(Pool::Io, 0, &url, "b"));
+        assert_eq!(None, pop(&scheduler, Pool::Io));
+    }
+}
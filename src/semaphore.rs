@@ -1,87 +1,488 @@
-use std::sync::{Condvar, Mutex};
+use std::cmp;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use futures::task::{self, Task};
 use num_cpus;
+use url::Url;
+
+use cancellation::CancellationToken;
+use error;
+use rate_limiter::RateLimiter;
+
+/// The requests per second and concurrency limits of a single host
+#[derive(Clone, Debug)]
+pub(crate) struct HostLimits {
+    pub(crate) requests_per_second: f64,
+    pub(crate) max_concurrent: usize,
+}
+
+#[derive(Debug)]
+struct HostState {
+    concurrent: usize,
+    max_concurrent: usize,
+    /// The number of documents that were started but haven't made their
+    /// first request yet (see `HostPermit::increment_requests`)
+    pending: usize,
+    paused_until: Option<Instant>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl HostState {
+    fn new(limits: Option<&HostLimits>) -> Self {
+        match limits {
+            Some(limits) => HostState {
+                concurrent: 0,
+                max_concurrent: cmp::max(limits.max_concurrent, 1),
+                pending: 0,
+                paused_until: None,
+                rate_limiter: Some(Arc::new(RateLimiter::new(limits.requests_per_second, 1))),
+            },
+            None => HostState {
+                concurrent: 0,
+                max_concurrent: usize::MAX,
+                pending: 0,
+                paused_until: None,
+                rate_limiter: None,
+            },
+        }
+    }
+
+    /// Returns `Ok` if a document for this host may start now, or else when it
+    /// may start (`None` if that is once a concurrent request slot is free). A
+    /// document may only start once the requests per second limit allows its
+    /// request after those of the documents already started.
+    fn readiness(&self, now: Instant) -> Result<(), Option<Instant>> {
+        match self.paused_until {
+            Some(deadline) if deadline > now => return Err(Some(deadline)),
+            _ => (),
+        }
+        if self.concurrent >= self.max_concurrent {
+            return Err(None);
+        }
+        if let Some(ref rate_limiter) = self.rate_limiter {
+            let ready_at = rate_limiter.ready_at(now, self.pending);
+            if ready_at > now {
+                return Err(Some(ready_at));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The tasks of the futures waiting for a slot, keyed by the id of their
+/// future (see `Semaphore::waiter`), so that a future that is polled again
+/// before it is notified is only notified once.
+#[derive(Debug, Default)]
+struct Waiters {
+    tasks: Mutex<HashMap<usize, Task>>,
+}
+
+impl Waiters {
+    /// Notifies the current task once a slot is released, replacing the task
+    /// `waiter` registered before.
+    fn register(&self, waiter: usize) {
+        self.tasks.lock().unwrap().insert(waiter, task::current());
+    }
+    /// Forgets the task of `waiter`, which doesn't wait anymore.
+    fn remove(&self, waiter: usize) {
+        self.tasks.lock().unwrap().remove(&waiter);
+    }
+    fn notify(&self) {
+        for (_, task) in self.tasks.lock().unwrap().drain() {
+            task.notify();
+        }
+    }
+}
 
 #[derive(Debug)]
 pub(crate) struct Semaphore {
-    max_requests_per_second: usize,
     max_threads_cpu: usize,
-    max_threads_io: usize,
+    host_limits: HashMap<String, HostLimits>,
+    default_host_limits: Option<HostLimits>,
 
-    requests: Mutex<usize>,
-    requests_condvar: Condvar,
+    hosts: Mutex<HashMap<String, HostState>>,
+    hosts_waiters: Waiters,
+    next_waiter: AtomicUsize,
+    rate_limiter: RateLimiter,
     threads_cpu: Mutex<usize>,
-    threads_cpu_condvar: Condvar,
-    threads_io: Mutex<usize>,
-    threads_io_condvar: Condvar,
+    threads_cpu_waiters: Waiters,
 }
 
 impl Default for Semaphore {
     fn default() -> Self {
         Semaphore {
-            max_requests_per_second: 10,
             max_threads_cpu: num_cpus::get(),
-            max_threads_io: 100,
+            host_limits: HashMap::new(),
+            default_host_limits: None,
 
-            requests: Mutex::new(0),
-            requests_condvar: Condvar::new(),
+            hosts: Mutex::new(HashMap::new()),
+            hosts_waiters: Waiters::default(),
+            next_waiter: AtomicUsize::new(0),
+            rate_limiter: RateLimiter::new(10.0, 1),
             threads_cpu: Mutex::new(0),
-            threads_cpu_condvar: Condvar::new(),
-            threads_io: Mutex::new(0),
-            threads_io_condvar: Condvar::new(),
+            threads_cpu_waiters: Waiters::default(),
         }
     }
 }
 
+/// A `HostPermit` holds one of the concurrent request slots of a host until
+/// it is dropped.
+#[derive(Debug)]
+pub(crate) struct HostPermit {
+    host: String,
+    /// Whether the first request of the document is still pending
+    pending: bool,
+    semaphore: Arc<Semaphore>,
+}
+
+impl HostPermit {
+    /// Blocks until the first request of the document that holds this permit
+    /// is allowed (see `Semaphore::increment_requests`).
+    pub(crate) fn increment_requests(&mut self, cancel: &CancellationToken) -> error::Result<()> {
+        self.semaphore
+            .wait_for_request(&self.host, &mut self.pending, cancel)
+    }
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        let mut hosts = self.semaphore.hosts.lock().unwrap();
+        if let Some(state) = hosts.get_mut(&self.host) {
+            state.concurrent -= 1;
+            if self.pending {
+                state.pending -= 1;
+            }
+        }
+        self.semaphore.hosts_waiters.notify();
+    }
+}
+
+/// A `CpuPermit` holds one of the cpu thread slots until it is dropped.
+#[derive(Debug)]
+pub(crate) struct CpuPermit {
+    semaphore: Arc<Semaphore>,
+}
+
+impl Drop for CpuPermit {
+    fn drop(&mut self) {
+        self.semaphore.decrement_threads_cpu();
+    }
+}
+
 impl Semaphore {
     pub(crate) fn new(
-        max_requests_per_second: usize,
+        requests_per_second: f64,
+        burst_size: usize,
         max_threads_cpu: usize,
-        max_threads_io: usize,
+        host_limits: HashMap<String, HostLimits>,
+        default_host_limits: Option<HostLimits>,
     ) -> Self {
-        let mut semaphore = Self::default();
-        semaphore.max_requests_per_second = max_requests_per_second;
-        semaphore.max_threads_cpu = max_threads_cpu;
-        semaphore.max_threads_io = max_threads_io;
-        semaphore
-    }
-    pub(crate) fn reset_requests(&self) {
-        let mut requests = self.requests.lock().unwrap();
-        *requests = 0;
-        self.requests_condvar.notify_one();
-    }
-    pub(crate) fn increment_requests(&self) {
-        let mut requests = self.requests.lock().unwrap();
-        while *requests >= self.max_requests_per_second {
-            requests = self.requests_condvar.wait(requests).unwrap()
-        }
-        *requests += 1;
-        self.requests_condvar.notify_one();
-    }
-    pub(crate) fn increment_threads_cpu(&self) {
+        Semaphore {
+            max_threads_cpu,
+            host_limits,
+            default_host_limits,
+            rate_limiter: RateLimiter::new(requests_per_second, burst_size),
+            ..Self::default()
+        }
+    }
+    fn host_state<'a>(
+        &self,
+        hosts: &'a mut HashMap<String, HostState>,
+        host: &str,
+    ) -> &'a mut HostState {
+        hosts.entry(host.to_string()).or_insert_with(|| {
+            let limits = self
+                .host_limits
+                .get(host)
+                .or(self.default_host_limits.as_ref());
+            HostState::new(limits)
+        })
+    }
+    /// Returns `Ok` if a document for `url` may start now: its host has a free
+    /// concurrent request slot and isn't paused. Otherwise, returns when it
+    /// may start, or `None` if that is once a slot is released.
+    pub(crate) fn host_ready(&self, url: &Url) -> Result<(), Option<Instant>> {
+        let mut hosts = self.hosts.lock().unwrap();
+        self.host_state(&mut hosts, &host(url))
+            .readiness(Instant::now())
+    }
+    /// Takes one of the concurrent request slots of the host of `url` if a
+    /// document for `url` may start now (see `host_ready`). Until the document
+    /// makes its first request through `HostPermit::increment_requests`, that
+    /// request counts as pending, so that the host isn't ready for another
+    /// document before the requests per second limit allows both.
+    pub(crate) fn try_acquire_host(
+        self: &Arc<Self>,
+        url: &Url,
+    ) -> Result<HostPermit, Option<Instant>> {
+        let host = host(url);
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = self.host_state(&mut hosts, &host);
+        state.readiness(Instant::now())?;
+        state.concurrent += 1;
+        state.pending += 1;
+        Ok(HostPermit {
+            host,
+            pending: true,
+            semaphore: self.clone(),
+        })
+    }
+    /// Takes one of the concurrent request slots of the host of `url`, whether
+    /// or not it has one free, for a document that won't make a request
+    /// because its batch was cancelled.
+    pub(crate) fn force_acquire_host(self: &Arc<Self>, url: &Url) -> HostPermit {
+        let host = host(url);
+        let mut hosts = self.hosts.lock().unwrap();
+        self.host_state(&mut hosts, &host).concurrent += 1;
+        HostPermit {
+            host,
+            pending: false,
+            semaphore: self.clone(),
+        }
+    }
+    /// Returns a new id for a future that waits for a slot, which it passes to
+    /// `poll_acquire_host` or `poll_increment_threads_cpu` every time it is
+    /// polled and to `forget_waiter` once it is dropped.
+    pub(crate) fn waiter(&self) -> usize {
+        self.next_waiter.fetch_add(1, Ordering::Relaxed)
+    }
+    /// Forgets `waiter`, a future that was dropped while waiting for a slot.
+    pub(crate) fn forget_waiter(&self, waiter: usize) {
+        self.hosts_waiters.remove(waiter);
+        self.threads_cpu_waiters.remove(waiter);
+    }
+    /// Takes one of the concurrent request slots of the host of `url`, for use
+    /// inside the `poll` of the future `waiter`. If the host has no free slot,
+    /// the current task is notified once a slot is released.
+    pub(crate) fn poll_acquire_host(
+        self: &Arc<Self>,
+        url: &Url,
+        waiter: usize,
+    ) -> Option<HostPermit> {
+        let host = host(url);
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = self.host_state(&mut hosts, &host);
+        if state.concurrent < state.max_concurrent {
+            state.concurrent += 1;
+            self.hosts_waiters.remove(waiter);
+            Some(HostPermit {
+                host,
+                pending: false,
+                semaphore: self.clone(),
+            })
+        } else {
+            self.hosts_waiters.register(waiter);
+            None
+        }
+    }
+    /// Stops new requests to the host of `url` until `duration` has elapsed
+    /// (or until an earlier, longer pause of the same host has elapsed).
+    pub(crate) fn pause_host(&self, url: &Url, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = self.host_state(&mut hosts, &host(url));
+        match state.paused_until {
+            Some(deadline) if deadline >= until => (),
+            _ => state.paused_until = Some(until),
+        }
+    }
+    /// Returns how long the host of `url` remains paused, if it is paused.
+    pub(crate) fn pause_remaining(&self, url: &Url) -> Option<Duration> {
+        self.host_pause_remaining(&host(url))
+    }
+    fn host_pause_remaining(&self, host: &str) -> Option<Duration> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = self.host_state(&mut hosts, host);
+        let remaining = state
+            .paused_until
+            .and_then(|deadline| deadline.checked_duration_since(Instant::now()));
+        if remaining.is_none() {
+            state.paused_until = None;
+        }
+        remaining
+    }
+    /// Reserves a request to `url` with the requests per second limit of its
+    /// host and returns how long the caller has to wait before making it.
+    pub(crate) fn reserve_host_request(&self, url: &Url) -> Duration {
+        self.reserve_host(&host(url), &mut false)
+    }
+    /// Same as `reserve_host_request`, for the request a document that was
+    /// started counts as pending if `pending` is set (which it unsets).
+    fn reserve_host(&self, host: &str, pending: &mut bool) -> Duration {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = self.host_state(&mut hosts, host);
+        if mem::replace(pending, false) {
+            state.pending -= 1;
+        }
+        state
+            .rate_limiter
+            .as_ref()
+            .map_or(Duration::from_secs(0), |limiter| limiter.reserve())
+    }
+    /// Reserves a request with the global requests per second limit and
+    /// returns how long the caller has to wait before making it.
+    pub(crate) fn reserve_global_request(&self) -> Duration {
+        self.rate_limiter.reserve()
+    }
+    /// Blocks until a request to `url` is allowed by the pause and requests
+    /// per second limit of its host, and then by the global requests per
+    /// second limit. The global limit is only reserved once the host allows
+    /// the request, so that waiting for a host doesn't hold up the requests
+    /// to other hosts. Returns a `CancelledError` as soon as `cancel` is
+    /// cancelled.
+    pub(crate) fn increment_requests(
+        &self,
+        url: &Url,
+        cancel: &CancellationToken,
+    ) -> error::Result<()> {
+        self.wait_for_request(&host(url), &mut false, cancel)
+    }
+    fn wait_for_request(
+        &self,
+        host: &str,
+        pending: &mut bool,
+        cancel: &CancellationToken,
+    ) -> error::Result<()> {
+        while let Some(remaining) = self.host_pause_remaining(host) {
+            cancel.sleep(remaining)?;
+        }
+        cancel.sleep(self.reserve_host(host, pending))?;
+        cancel.sleep(self.reserve_global_request())
+    }
+
+    /// Takes one of the cpu thread slots, for use inside the `poll` of the
+    /// future `waiter`. If no slot is free, the current task is notified once
+    /// a slot is released.
+    pub(crate) fn poll_increment_threads_cpu(self: &Arc<Self>, waiter: usize) -> Option<CpuPermit> {
         let mut threads_cpu = self.threads_cpu.lock().unwrap();
-        while *threads_cpu >= self.max_threads_cpu {
-            threads_cpu = self.threads_cpu_condvar.wait(threads_cpu).unwrap()
+        if *threads_cpu < self.max_threads_cpu {
+            *threads_cpu += 1;
+            self.threads_cpu_waiters.remove(waiter);
+            Some(CpuPermit {
+                semaphore: self.clone(),
+            })
+        } else {
+            self.threads_cpu_waiters.register(waiter);
+            None
         }
-        *threads_cpu += 1;
-        self.threads_cpu_condvar.notify_one();
     }
     pub(crate) fn decrement_threads_cpu(&self) {
         let mut threads_cpu = self.threads_cpu.lock().unwrap();
         *threads_cpu -= 1;
-        self.threads_cpu_condvar.notify_one();
+        self.threads_cpu_waiters.notify();
     }
-    pub(crate) fn increment_threads_io(&self) {
-        let mut threads_io = self.threads_io.lock().unwrap();
-        while *threads_io >= self.max_threads_io {
-            threads_io = self.threads_io_condvar.wait(threads_io).unwrap()
-        }
-        *threads_io += 1;
-        self.threads_io_condvar.notify_one();
+}
+
+/// Returns the host of `url` as the limits of hosts are keyed.
+pub(crate) fn host(url: &Url) -> String {
+    url.host_str().unwrap_or_default().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::{future, Future};
+
+    fn semaphore() -> Semaphore {
+        let mut host_limits = HashMap::new();
+        host_limits.insert(
+            "slow.example.com".to_string(),
+            HostLimits {
+                requests_per_second: 0.0,
+                max_concurrent: 1,
+            },
+        );
+        let default_host_limits = HostLimits {
+            requests_per_second: 0.0,
+            max_concurrent: 2,
+        };
+        Semaphore::new(0.0, 1, 1, host_limits, Some(default_host_limits))
+    }
+
+    fn available(semaphore: &Semaphore, url: &Url) -> usize {
+        let mut hosts = semaphore.hosts.lock().unwrap();
+        let state = semaphore.host_state(&mut hosts, &host(url));
+        state.max_concurrent - state.concurrent
     }
-    pub(crate) fn decrement_threads_io(&self) {
-        let mut threads_io = self.threads_io.lock().unwrap();
-        *threads_io -= 1;
-        self.threads_io_condvar.notify_one();
+
+    #[test]
+    fn test_host_permits() {
+        let semaphore = Arc::new(semaphore());
+        let slow = Url::parse("https://SLOW.example.com/a").unwrap();
+        let fast = Url::parse("https://fast.example.com/a").unwrap();
+
+        let permit = semaphore.try_acquire_host(&slow).unwrap();
+        assert_eq!(0, available(&semaphore, &slow));
+        assert_eq!(2, available(&semaphore, &fast));
+        assert_eq!(Err(None), semaphore.host_ready(&slow));
+        assert!(semaphore.try_acquire_host(&slow).is_err());
+        drop(permit);
+        assert_eq!(1, available(&semaphore, &slow));
+
+        let permits = vec![
+            semaphore.try_acquire_host(&fast).unwrap(),
+            semaphore.try_acquire_host(&fast).unwrap(),
+        ];
+        assert_eq!(0, available(&semaphore, &fast));
+        drop(permits);
+        assert_eq!(2, available(&semaphore, &fast));
+    }
+
+    #[test]
+    fn test_waiters() {
+        let semaphore = Arc::new(semaphore());
+        let url = Url::parse("https://slow.example.com/a").unwrap();
+        let (host, cpu) = (semaphore.waiter(), semaphore.waiter());
+        let _host_permit = semaphore.try_acquire_host(&url).unwrap();
+        let cpu_permit = semaphore.poll_increment_threads_cpu(cpu);
+        assert!(cpu_permit.is_some());
+        let semaphore = semaphore.clone();
+        future::lazy(move || {
+            // A future that is polled again before it is notified only
+            // registers its task once.
+            for _ in 0..3 {
+                assert!(semaphore.poll_acquire_host(&url, host).is_none());
+                assert!(semaphore.poll_increment_threads_cpu(cpu).is_none());
+            }
+            assert_eq!(1, semaphore.hosts_waiters.tasks.lock().unwrap().len());
+            assert_eq!(1, semaphore.threads_cpu_waiters.tasks.lock().unwrap().len());
+
+            drop(cpu_permit);
+            assert!(semaphore
+                .threads_cpu_waiters
+                .tasks
+                .lock()
+                .unwrap()
+                .is_empty());
+            semaphore.forget_waiter(host);
+            assert!(semaphore.hosts_waiters.tasks.lock().unwrap().is_empty());
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_pause_host() {
+        let semaphore = semaphore();
+        let url = Url::parse("https://fast.example.com/a").unwrap();
+        semaphore.pause_host(&url, Duration::from_millis(100));
+        match semaphore.host_ready(&url) {
+            Err(Some(at)) => assert!(at > Instant::now()),
+            readiness => panic!("expected a paused host, got {:?}", readiness),
+        }
+        let start = Instant::now();
+        let cancel = CancellationToken::new();
+        assert!(semaphore.increment_requests(&url, &cancel).is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        semaphore.pause_host(&url, Duration::from_secs(60));
+        cancel.cancel();
+        assert!(semaphore.increment_requests(&url, &cancel).is_err());
     }
 }
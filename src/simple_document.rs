@@ -1,36 +1,105 @@
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
 
 use url::Url;
 
 use document::Document;
+use output::Output;
+use output_format::OutputFormat;
+use response_info::ResponseInfo;
 
 /// `SimpleDocument` is a model struct implementing the `Document` trait.
 /// Although you can certainly use this struct, you may want to consider writing
 /// your own simple struct implementing `Document` in order to provide more
 /// customized behavior.
-#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd, Hash)]
+///
+/// `SimpleDocument`s are compared and hashed by everything but their
+/// `ResponseInfo`.
+#[derive(Clone, Debug)]
 pub struct SimpleDocument {
     bytes: Option<Vec<u8>>,
+    output_format: OutputFormat,
+    outputs: Vec<Output>,
     path: PathBuf,
+    response: Option<ResponseInfo>,
     url: Url,
-    wkhtmltopdf: bool,
 }
 
 impl SimpleDocument {
-    /// Creates a new `SimpleDocument`
+    /// Creates a new `SimpleDocument` that is converted to PDF if
+    /// `wkhtmltopdf` is `true` and written as is otherwise
     pub fn new(path: PathBuf, url: Url, wkhtmltopdf: bool) -> Self {
+        SimpleDocument::with_output_format(path, url, OutputFormat::from(wkhtmltopdf))
+    }
+
+    /// Creates a new `SimpleDocument` that is written to disk in `output_format`
+    pub fn with_output_format(path: PathBuf, url: Url, output_format: OutputFormat) -> Self {
         SimpleDocument {
             bytes: None,
+            output_format,
+            outputs: Vec::new(),
             path,
+            response: None,
             url,
-            wkhtmltopdf,
         }
     }
+
+    /// Adds an `Output` the document is written to besides `path`, produced
+    /// from the same fetch (e.g. a PDF next to the raw HTML)
+    pub fn add_output(mut self, output: Output) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
     /// If `SimpleDocument` has already been downloaded by `Client`, will
     /// return `Some(bytes)`; otherwise will return `None`
     pub fn bytes(&self) -> Option<&[u8]> {
         self.bytes.as_ref().map(|v| &v[..])
     }
+
+    /// If `SimpleDocument` has already been fetched (or converted) by
+    /// `Client`, will return `Some(response)` describing the response (e.g.
+    /// its final URL and `Last-Modified` date); otherwise will return `None`
+    pub fn response(&self) -> Option<&ResponseInfo> {
+        self.response.as_ref()
+    }
+
+    fn key(&self) -> (&Option<Vec<u8>>, OutputFormat, &[Output], &Path, &Url) {
+        (
+            &self.bytes,
+            self.output_format,
+            &self.outputs,
+            &self.path,
+            &self.url,
+        )
+    }
+}
+
+impl PartialEq for SimpleDocument {
+    fn eq(&self, other: &SimpleDocument) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SimpleDocument {}
+
+impl PartialOrd for SimpleDocument {
+    fn partial_cmp(&self, other: &SimpleDocument) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SimpleDocument {
+    fn cmp(&self, other: &SimpleDocument) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl Hash for SimpleDocument {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
 }
 
 impl Document for SimpleDocument {
@@ -40,10 +109,40 @@ impl Document for SimpleDocument {
     fn url(&self) -> &Url {
         &self.url
     }
+    #[allow(deprecated)]
     fn wkhtmltopdf(&self) -> bool {
-        self.wkhtmltopdf
+        self.output_format == OutputFormat::Pdf
+    }
+    fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+    fn outputs(&self) -> Vec<Output> {
+        let mut outputs = vec![Output::new(self.path.clone(), self.output_format)];
+        outputs.extend(self.outputs.iter().cloned());
+        outputs
     }
     fn set_bytes(&mut self, bytes: Option<Vec<u8>>) {
         self.bytes = bytes
     }
+    fn set_response(&mut self, response: ResponseInfo) {
+        self.response = Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_wkhtmltopdf() {
+        let url = Url::parse("https://www.sec.gov/robots.txt").unwrap();
+        let document = SimpleDocument::new(PathBuf::from("robots.pdf"), url.clone(), true);
+        assert!(document.wkhtmltopdf());
+        assert_eq!(OutputFormat::Pdf, document.output_format());
+
+        let document = SimpleDocument::new(PathBuf::from("robots.txt"), url, false);
+        assert!(!document.wkhtmltopdf());
+        assert_eq!(OutputFormat::Raw, document.output_format());
+    }
 }
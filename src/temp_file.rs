@@ -0,0 +1,250 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{self, DirEntry, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use error::Result;
+
+const PREFIX: &str = ".urls2disk-";
+
+cfg_if! {
+    if #[cfg(unix)] {
+        use std::io;
+
+        use libc;
+
+        /// Returns whether the temp file `entry` of process `pid` was left
+        /// behind, i.e. whether that process is no longer running.
+        fn is_stale(pid: u32, _entry: &DirEntry) -> bool {
+            // A pid that doesn't fit a `pid_t` (or `0`) would make `kill`
+            // signal a process group instead.
+            if pid == 0 || pid > libc::pid_t::MAX as u32 {
+                return true;
+            }
+            // Signal `0` only checks that the process exists; `EPERM` means
+            // that it does, but belongs to another user.
+            let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+            result != 0 && io::Error::last_os_error().raw_os_error() != Some(libc::EPERM)
+        }
+    } else {
+        use std::time::Duration;
+
+        /// Returns whether the temp file `entry` of process `pid` was left
+        /// behind. Without a way to check other processes, that's the case
+        /// once it hasn't been written to for a day.
+        fn is_stale(pid: u32, entry: &DirEntry) -> bool {
+            let age = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok());
+            match age {
+                Some(age) => pid != process::id() && age > Duration::from_secs(24 * 60 * 60),
+                None => false,
+            }
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(unix)] {
+        fn sync_directory(directory: &Path) -> Result<()> {
+            File::open(directory)?.sync_all()?;
+            Ok(())
+        }
+    } else {
+        fn sync_directory(_directory: &Path) -> Result<()> {
+            Ok(())
+        }
+    }
+}
+
+/// `TempFiles` hands out the temp files documents are written to before they
+/// are renamed into place. The first time it sees a directory, it removes the
+/// temp files that earlier, interrupted runs left behind in it.
+#[derive(Debug, Default)]
+pub(crate) struct TempFiles {
+    counter: AtomicUsize,
+    cleaned: Mutex<HashSet<PathBuf>>,
+}
+
+impl TempFiles {
+    /// Returns a new `TempFile` next to `target`, named
+    /// `.urls2disk-{pid}-{n}-{file name of target}` so that it keeps the
+    /// extension of `target`.
+    pub(crate) fn create<P: AsRef<Path>>(&self, target: P) -> Result<TempFile> {
+        let target = target.as_ref();
+        let directory = directory(target);
+        let file_name = target
+            .file_name()
+            .ok_or_else(|| format_err!("{:?} has no file name", target))?;
+        self.clean(&directory);
+        let n = self.counter.fetch_add(1, Ordering::SeqCst);
+        let mut name = OsString::from(format!("{}{}-{}-", PREFIX, process::id(), n));
+        name.push(file_name);
+        Ok(TempFile {
+            path: directory.join(name),
+            target: target.to_path_buf(),
+            persisted: false,
+        })
+    }
+
+    fn clean(&self, directory: &Path) {
+        let mut cleaned = self.cleaned.lock().unwrap();
+        if cleaned.contains(directory) {
+            return;
+        }
+        cleaned.insert(directory.to_path_buf());
+        if let Err(e) = remove_stale(directory) {
+            warn!("failed to clean up temp files in {:?}: {}", directory, e);
+        }
+    }
+}
+
+/// A `TempFile` is the path a document is written to before it is complete.
+/// `persist` flushes it to disk and renames it to its target; if it is dropped
+/// without being persisted, whatever was written to it is removed.
+#[derive(Debug)]
+pub(crate) struct TempFile {
+    path: PathBuf,
+    target: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Fsyncs the temp file, renames it to its target and fsyncs the
+    /// directory, so that the target is either absent or complete.
+    pub(crate) fn persist(mut self) -> Result<()> {
+        persist(&self.path, &self.target)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted && self.path.exists() {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("failed to remove temp file {:?}: {}", &self.path, e);
+            }
+        }
+    }
+}
+
+/// Fsyncs `path`, renames it to `target` and fsyncs the directory of `target`.
+pub(crate) fn persist<P: AsRef<Path>, Q: AsRef<Path>>(path: P, target: Q) -> Result<()> {
+    OpenOptions::new().write(true).open(&path)?.sync_all()?;
+    fs::rename(&path, &target)?;
+    sync_directory(&directory(target.as_ref()))
+}
+
+fn directory(target: &Path) -> PathBuf {
+    match target.parent() {
+        Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Removes the temp files in `directory` that belong to processes that are no
+/// longer running.
+fn remove_stale(directory: &Path) -> Result<()> {
+    if !directory.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let pid = name
+            .to_str()
+            .filter(|name| name.starts_with(PREFIX))
+            .and_then(|name| name[PREFIX.len()..].split('-').next())
+            .and_then(|pid| pid.parse::<u32>().ok());
+        match pid {
+            Some(pid) if is_stale(pid, &entry) => {
+                debug!("removing stale temp file {:?}", entry.path());
+                fs::remove_file(entry.path())?;
+            },
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::io::Write;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join("urls2disk").join(name);
+        if directory.exists() {
+            fs::remove_dir_all(&directory).unwrap();
+        }
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn test_persist() {
+        let directory = directory("test_temp_file_persist");
+        let target = directory.join("test.html");
+        let temp_files = TempFiles::default();
+
+        let temp_file = temp_files.create(&target).unwrap();
+        assert!(temp_file.path().to_str().unwrap().ends_with("-test.html"));
+        File::create(temp_file.path())
+            .unwrap()
+            .write_all(b"partial")
+            .unwrap();
+        drop(temp_file);
+        assert!(!target.exists());
+        assert_eq!(0, fs::read_dir(&directory).unwrap().count());
+
+        let temp_file = temp_files.create(&target).unwrap();
+        File::create(temp_file.path())
+            .unwrap()
+            .write_all(b"complete")
+            .unwrap();
+        temp_file.persist().unwrap();
+        assert_eq!(b"complete".to_vec(), fs::read(&target).unwrap());
+        assert_eq!(1, fs::read_dir(&directory).unwrap().count());
+    }
+
+    #[test]
+    fn test_remove_stale() {
+        let directory = directory("test_temp_file_remove_stale");
+        let stale = directory.join(format!("{}{}-0-test.html", PREFIX, u32::MAX));
+        let live = directory.join(format!("{}{}-0-test.html", PREFIX, process::id()));
+        let other = directory.join("test.html");
+        for path in &[&stale, &live, &other] {
+            File::create(path).unwrap();
+        }
+        let temp_files = TempFiles::default();
+        let _temp_file = temp_files.create(&other).unwrap();
+        assert!(!stale.exists());
+        assert!(live.exists());
+        assert!(other.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_keep_other_process() {
+        use std::os::unix::process::parent_id;
+
+        let directory = directory("test_temp_file_keep_other_process");
+        let other = directory.join(format!("{}{}-0-test.html", PREFIX, parent_id()));
+        File::create(&other).unwrap();
+        let temp_files = TempFiles::default();
+        let _temp_file = temp_files.create(directory.join("test.html")).unwrap();
+        assert!(other.exists());
+    }
+}
@@ -0,0 +1,143 @@
+use std::cmp;
+use std::time::Duration;
+
+use error::TimeoutKind;
+
+/// `Timeouts` bound how long a single attempt at a document may take. Every
+/// timeout is optional; an unset timeout never expires. A document that
+/// exceeds one of them fails with a `TimeoutError` (which the default
+/// `RetryPolicy` retries).
+///
+/// The client's timeouts are set with `ClientBuilder::set_timeouts`; a
+/// `Document` can override some of them for known slow endpoints through
+/// `Document::timeouts`.
+///
+/// The blocking `Client` can only give reqwest a single timeout for both
+/// connecting and reading, so it applies the longer of the connect and read
+/// timeouts to both (a connect timeout of a `Document` that is shorter than the
+/// read timeout has no effect there), and reports a server that stalls before
+/// sending the response headers as `TimeoutKind::Connect`. It checks the total
+/// timeout between reads, so a download can exceed it by up to that timeout;
+/// with neither a connect nor a read timeout, it applies the total timeout to
+/// connecting and to every read instead. `AsyncClient` applies each timeout
+/// separately.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Timeouts {
+    connect: Option<Duration>,
+    read: Option<Duration>,
+    total: Option<Duration>,
+    conversion: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Creates `Timeouts` with every timeout unset
+    pub fn new() -> Timeouts {
+        Timeouts::default()
+    }
+
+    /// Set the time allowed to connect to the server and receive the response
+    /// headers.
+    pub fn set_connect(mut self, connect: Duration) -> Timeouts {
+        self.connect = Some(connect);
+        self
+    }
+
+    /// Set the time allowed between two chunks of a response body.
+    pub fn set_read(mut self, read: Duration) -> Timeouts {
+        self.read = Some(read);
+        self
+    }
+
+    /// Set the time allowed for a whole download, from sending the request to
+    /// writing the last byte to disk.
+    pub fn set_total(mut self, total: Duration) -> Timeouts {
+        self.total = Some(total);
+        self
+    }
+
+    /// Set the time allowed for the converter (e.g. wkhtmltopdf) to convert a
+    /// document. The converter is killed when it expires.
+    pub fn set_conversion(mut self, conversion: Duration) -> Timeouts {
+        self.conversion = Some(conversion);
+        self
+    }
+
+    /// Returns the connect timeout, if it is set
+    pub fn connect(&self) -> Option<Duration> {
+        self.connect
+    }
+
+    /// Returns the read timeout, if it is set
+    pub fn read(&self) -> Option<Duration> {
+        self.read
+    }
+
+    /// Returns the total timeout, if it is set
+    pub fn total(&self) -> Option<Duration> {
+        self.total
+    }
+
+    /// Returns the conversion timeout, if it is set
+    pub fn conversion(&self) -> Option<Duration> {
+        self.conversion
+    }
+
+    /// Returns these timeouts with the ones that are unset taken from
+    /// `defaults`.
+    pub(crate) fn or(self, defaults: Timeouts) -> Timeouts {
+        Timeouts {
+            connect: self.connect.or(defaults.connect),
+            read: self.read.or(defaults.read),
+            total: self.total.or(defaults.total),
+            conversion: self.conversion.or(defaults.conversion),
+        }
+    }
+
+    /// Returns the single timeout a blocking `reqwest::Client` applies to
+    /// connecting and to every read: the longer of the connect and read
+    /// timeouts, whichever of them is set, or else the total timeout, so that
+    /// a server that stalls can't hold up a download past it.
+    pub(crate) fn io(&self) -> Option<Duration> {
+        match (self.connect, self.read) {
+            (Some(connect), Some(read)) => Some(cmp::max(connect, read)),
+            (None, None) => self.total,
+            (connect, read) => connect.or(read),
+        }
+    }
+
+    /// Returns the kind of timeout that expired when the timeout returned by
+    /// `io` expires while waiting for `kind`.
+    pub(crate) fn io_kind(&self, kind: TimeoutKind) -> TimeoutKind {
+        match (self.connect, self.read) {
+            (None, None) => TimeoutKind::Total,
+            _ => kind,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_or() {
+        let defaults = Timeouts::new()
+            .set_connect(Duration::from_secs(30))
+            .set_read(Duration::from_secs(30));
+        let timeouts = Timeouts::new()
+            .set_read(Duration::from_secs(120))
+            .set_conversion(Duration::from_secs(60))
+            .or(defaults);
+        assert_eq!(Some(Duration::from_secs(30)), timeouts.connect());
+        assert_eq!(Some(Duration::from_secs(120)), timeouts.read());
+        assert_eq!(None, timeouts.total());
+        assert_eq!(Some(Duration::from_secs(60)), timeouts.conversion());
+        assert_eq!(Some(Duration::from_secs(120)), timeouts.io());
+        assert_eq!(None, Timeouts::new().io());
+        assert_eq!(TimeoutKind::Read, timeouts.io_kind(TimeoutKind::Read));
+
+        let timeouts = Timeouts::new().set_total(Duration::from_secs(300));
+        assert_eq!(Some(Duration::from_secs(300)), timeouts.io());
+        assert_eq!(TimeoutKind::Total, timeouts.io_kind(TimeoutKind::Read));
+    }
+}
@@ -1,10 +1,33 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
 use std::time::Duration;
 
+use error::Result;
+
 pub(crate) fn duration_to_millis(duration: Duration) -> u64 {
     let seconds = duration.as_secs();
     let nanos = duration.subsec_nanos() as u64;
-    let millis = (seconds * 1_000) + (nanos / 1_000_000);
-    millis
+    (seconds * 1_000) + (nanos / 1_000_000)
+}
+
+pub(crate) fn duration_to_nanos(duration: Duration) -> u64 {
+    duration
+        .as_secs()
+        .saturating_mul(1_000_000_000)
+        .saturating_add(u64::from(duration.subsec_nanos()))
+}
+
+pub(crate) fn nanos_to_duration(nanos: u64) -> Duration {
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+pub(crate) fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    Ok(bytes)
 }
 
 #[cfg(test)]
@@ -18,4 +41,12 @@ mod tests {
         let millis = duration_to_millis(duration);
         assert_eq!(expected, millis)
     }
+
+    #[test]
+    fn test_duration_to_nanos() {
+        let duration = Duration::new(3, 5);
+        let nanos = duration_to_nanos(duration);
+        assert_eq!(3_000_000_005, nanos);
+        assert_eq!(duration, nanos_to_duration(nanos));
+    }
 }
@@ -0,0 +1,153 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use reqwest::header::{
+    ETag, EntityTag, Headers, HttpDate, IfModifiedSince, IfNoneMatch, IfRange, LastModified,
+};
+
+use error::Result;
+
+/// The validators (`ETag` and `Last-Modified`) a server sent along with a
+/// response, which identify the version of the resource it returned
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Validators {
+    pub(crate) etag: Option<EntityTag>,
+    pub(crate) last_modified: Option<HttpDate>,
+}
+
+impl Validators {
+    /// Returns the validators of a response, or `None` if it has neither.
+    pub(crate) fn from_headers(headers: &Headers) -> Option<Validators> {
+        let validators = Validators {
+            etag: headers.get::<ETag>().map(|etag| etag.0.clone()),
+            last_modified: headers.get::<LastModified>().map(|date| date.0),
+        };
+        if validators.etag.is_none() && validators.last_modified.is_none() {
+            None
+        } else {
+            Some(validators)
+        }
+    }
+
+    /// Reads validators written by `write`. Returns `None` if the file doesn't
+    /// exist or holds no validators.
+    pub(crate) fn read<P: AsRef<Path>>(path: P) -> Option<Validators> {
+        let contents = fs::read_to_string(path).ok()?;
+        let mut validators = Validators::default();
+        for line in contents.lines() {
+            let mut parts = line.splitn(2, ": ");
+            match (parts.next(), parts.next()) {
+                (Some("ETag"), Some(value)) => validators.etag = value.parse().ok(),
+                (Some("Last-Modified"), Some(value)) => {
+                    validators.last_modified = value.parse().ok()
+                },
+                _ => (),
+            }
+        }
+        if validators.etag.is_none() && validators.last_modified.is_none() {
+            None
+        } else {
+            Some(validators)
+        }
+    }
+
+    /// Writes the validators to `path`, one header per line.
+    pub(crate) fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        if let Some(ref etag) = self.etag {
+            writeln!(writer, "ETag: {}", etag)?;
+        }
+        if let Some(ref last_modified) = self.last_modified {
+            writeln!(writer, "Last-Modified: {}", last_modified)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Returns the `ETag` and `Last-Modified` headers the validators were read
+    /// from.
+    pub(crate) fn to_headers(&self) -> Headers {
+        let mut headers = Headers::new();
+        if let Some(ref etag) = self.etag {
+            headers.set(ETag(etag.clone()));
+        }
+        if let Some(last_modified) = self.last_modified {
+            headers.set(LastModified(last_modified));
+        }
+        headers
+    }
+
+    /// Returns the `If-None-Match` and `If-Modified-Since` headers that make
+    /// the server answer `304 Not Modified` if the resource hasn't changed.
+    pub(crate) fn conditional_headers(&self) -> Headers {
+        let mut headers = Headers::new();
+        if let Some(ref etag) = self.etag {
+            headers.set(IfNoneMatch::Items(vec![etag.clone()]));
+        }
+        if let Some(last_modified) = self.last_modified {
+            headers.set(IfModifiedSince(last_modified));
+        }
+        headers
+    }
+
+    /// Returns the `If-Range` header to resume a download of this version of
+    /// the resource. Weak entity tags can't be used with `If-Range`, so the
+    /// `Last-Modified` date is used instead if there is no strong one.
+    pub(crate) fn if_range(&self) -> Option<IfRange> {
+        match self.etag {
+            Some(ref etag) if !etag.weak => Some(IfRange::EntityTag(etag.clone())),
+            _ => self.last_modified.map(IfRange::Date),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    #[test]
+    fn test_read_write() {
+        let directory = env::temp_dir().join("urls2disk");
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("test_validators_read_write");
+
+        let mut headers = Headers::new();
+        assert_eq!(None, Validators::from_headers(&headers));
+        headers.set_raw("ETag", "\"abc\"");
+        headers.set_raw("Last-Modified", "Wed, 21 Oct 2015 07:28:00 GMT");
+        let validators = Validators::from_headers(&headers).unwrap();
+        validators.write(&path).unwrap();
+        assert_eq!(Some(validators), Validators::read(&path));
+
+        fs::write(&path, "").unwrap();
+        assert_eq!(None, Validators::read(&path));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(None, Validators::read(&path));
+    }
+
+    #[test]
+    fn test_if_range() {
+        let date: HttpDate = "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap();
+        let strong = Validators {
+            etag: Some(EntityTag::strong("abc".to_string())),
+            last_modified: Some(date),
+        };
+        let weak = Validators {
+            etag: Some(EntityTag::weak("abc".to_string())),
+            last_modified: Some(date),
+        };
+        let weak_only = Validators {
+            etag: Some(EntityTag::weak("abc".to_string())),
+            last_modified: None,
+        };
+        assert_eq!(
+            Some(IfRange::EntityTag(EntityTag::strong("abc".to_string()))),
+            strong.if_range()
+        );
+        assert_eq!(Some(IfRange::Date(date)), weak.if_range());
+        assert_eq!(None, weak_only.if_range());
+    }
+}
@@ -12,125 +12,186 @@ cfg_if! {
     }
 }
 
-#[derive(Clone, Debug)]
-pub(crate) struct Settings {
-    disable_external_links: bool,
-    disable_javascript: bool,
-    enable_forms: bool,
-    dpi: usize,
-    grayscale: bool,
-    image_dpi: usize,
-    image_quality: usize,
-    low_quality: bool,
-    javascript_delay: Duration,
-    margin_bottom: String,
-    margin_left: String,
-    margin_right: String,
-    margin_top: String,
-    no_background: bool,
-    no_images: bool,
-    no_pdf_compression: bool,
-    orientation: Orientation,
-    page_size: PageSize,
-    zoom: f32,
+/// `Settings` are the wkhtmltopdf settings (see `Setting`) used to convert a
+/// document. Every setting is optional; an unset setting takes its default
+/// value. Images are converted with wkhtmltoimage, which only uses the
+/// settings that don't concern pages.
+///
+/// The client's settings are set with `ClientBuilder::set_wkhtmltopdf_setting`;
+/// a `Document` can override some of them through
+/// `Document::wkhtmltopdf_settings`, e.g. to print wide tables in landscape.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    disable_external_links: Option<bool>,
+    disable_javascript: Option<bool>,
+    enable_forms: Option<bool>,
+    dpi: Option<usize>,
+    grayscale: Option<bool>,
+    image_dpi: Option<usize>,
+    image_quality: Option<usize>,
+    low_quality: Option<bool>,
+    javascript_delay: Option<Duration>,
+    margin_bottom: Option<String>,
+    margin_left: Option<String>,
+    margin_right: Option<String>,
+    margin_top: Option<String>,
+    no_background: Option<bool>,
+    no_images: Option<bool>,
+    no_pdf_compression: Option<bool>,
+    orientation: Option<Orientation>,
+    page_size: Option<PageSize>,
+    zoom: Option<f32>,
 }
 
-impl Default for Settings {
-    fn default() -> Settings {
+impl Settings {
+    /// Creates `Settings` with every setting unset
+    pub fn new() -> Settings {
+        Settings::default()
+    }
+
+    /// Set a setting.
+    pub fn set(mut self, setting: Setting) -> Settings {
+        use self::Setting::*;
+        match setting {
+            DisableExternalLinks(v) => self.disable_external_links = Some(v),
+            DisableJavascript(v) => self.disable_javascript = Some(v),
+            EnableForms(v) => self.enable_forms = Some(v),
+            Dpi(v) => self.dpi = Some(v),
+            Grayscale(v) => self.grayscale = Some(v),
+            ImageDpi(v) => self.image_dpi = Some(v),
+            ImageQuality(v) => self.image_quality = Some(v),
+            LowQuality(v) => self.low_quality = Some(v),
+            JavascriptDelay(v) => self.javascript_delay = Some(v),
+            MarginBottom(v) => self.margin_bottom = Some(v),
+            MarginLeft(v) => self.margin_left = Some(v),
+            MarginRight(v) => self.margin_right = Some(v),
+            MarginTop(v) => self.margin_top = Some(v),
+            NoBackground(v) => self.no_background = Some(v),
+            NoImages(v) => self.no_images = Some(v),
+            NoPdfCompression(v) => self.no_pdf_compression = Some(v),
+            Orientation(v) => self.orientation = Some(v),
+            PageSize(v) => self.page_size = Some(v),
+            Zoom(v) => self.zoom = Some(v),
+        };
+        self
+    }
+
+    /// Returns these settings with the ones that are unset taken from
+    /// `defaults`.
+    pub(crate) fn or(self, defaults: &Settings) -> Settings {
+        let defaults = defaults.clone();
         Settings {
-            disable_external_links: false,
-            disable_javascript: false,
-            enable_forms: false,
-            dpi: 96,
-            grayscale: false,
-            image_dpi: 600,
-            image_quality: 94,
-            low_quality: false,
-            javascript_delay: Duration::from_millis(200),
-            margin_bottom: String::from("0.5in"),
-            margin_left: String::from("0.5in"),
-            margin_right: String::from("0.5in"),
-            margin_top: String::from("0.5in"),
-            no_background: false,
-            no_images: false,
-            no_pdf_compression: false,
-            orientation: Orientation::Portrait,
-            page_size: PageSize::Letter,
-            zoom: default_zoom(),
+            disable_external_links: self
+                .disable_external_links
+                .or(defaults.disable_external_links),
+            disable_javascript: self.disable_javascript.or(defaults.disable_javascript),
+            enable_forms: self.enable_forms.or(defaults.enable_forms),
+            dpi: self.dpi.or(defaults.dpi),
+            grayscale: self.grayscale.or(defaults.grayscale),
+            image_dpi: self.image_dpi.or(defaults.image_dpi),
+            image_quality: self.image_quality.or(defaults.image_quality),
+            low_quality: self.low_quality.or(defaults.low_quality),
+            javascript_delay: self.javascript_delay.or(defaults.javascript_delay),
+            margin_bottom: self.margin_bottom.or(defaults.margin_bottom),
+            margin_left: self.margin_left.or(defaults.margin_left),
+            margin_right: self.margin_right.or(defaults.margin_right),
+            margin_top: self.margin_top.or(defaults.margin_top),
+            no_background: self.no_background.or(defaults.no_background),
+            no_images: self.no_images.or(defaults.no_images),
+            no_pdf_compression: self.no_pdf_compression.or(defaults.no_pdf_compression),
+            orientation: self.orientation.or(defaults.orientation),
+            page_size: self.page_size.or(defaults.page_size),
+            zoom: self.zoom.or(defaults.zoom),
         }
     }
-}
 
-impl Settings {
     pub(crate) fn to_arguments(&self) -> Vec<String> {
         let mut arguments = Vec::new();
-        if self.disable_external_links {
+        if self.disable_external_links.unwrap_or(false) {
             arguments.push("--disable-external-links".to_string());
         }
-        if self.disable_javascript {
+        if self.disable_javascript.unwrap_or(false) {
             arguments.push("--disable-javascript".to_string());
         }
-        if self.enable_forms {
+        if self.enable_forms.unwrap_or(false) {
             arguments.push("--enable-forms".to_string());
         }
-        arguments.extend_from_slice(&["--dpi".to_string(), self.dpi.to_string()]);
-        if self.grayscale {
+        arguments.extend_from_slice(&["--dpi".to_string(), self.dpi.unwrap_or(96).to_string()]);
+        if self.grayscale.unwrap_or(false) {
             arguments.push("--grayscale".to_string());
         }
-        arguments.extend_from_slice(&["--image-dpi".to_string(), self.image_dpi.to_string()]);
+        arguments.extend_from_slice(&[
+            "--image-dpi".to_string(),
+            self.image_dpi.unwrap_or(600).to_string(),
+        ]);
         arguments.extend_from_slice(&[
             "--image-quality".to_string(),
-            self.image_quality.to_string(),
+            self.image_quality.unwrap_or(94).to_string(),
         ]);
-        if self.low_quality {
+        if self.low_quality.unwrap_or(false) {
             arguments.push("--low-quality".to_string());
         }
+        let javascript_delay = self
+            .javascript_delay
+            .unwrap_or_else(|| Duration::from_millis(200));
         arguments.extend_from_slice(&[
             "--javascript-delay".to_string(),
-            duration_to_millis(self.javascript_delay).to_string(),
+            duration_to_millis(javascript_delay).to_string(),
         ]);
-        arguments.extend_from_slice(&["--margin-bottom".to_string(), self.margin_bottom.clone()]);
-        arguments.extend_from_slice(&["--margin-left".to_string(), self.margin_left.clone()]);
-        arguments.extend_from_slice(&["--margin-right".to_string(), self.margin_right.clone()]);
-        arguments.extend_from_slice(&["--margin-top".to_string(), self.margin_top.clone()]);
-        if self.no_background {
+        for (name, margin) in &[
+            ("--margin-bottom", &self.margin_bottom),
+            ("--margin-left", &self.margin_left),
+            ("--margin-right", &self.margin_right),
+            ("--margin-top", &self.margin_top),
+        ] {
+            arguments.extend_from_slice(&[
+                name.to_string(),
+                margin.as_deref().unwrap_or("0.5in").to_string(),
+            ]);
+        }
+        if self.no_background.unwrap_or(false) {
             arguments.push("--no-background".to_string());
         }
-        if self.no_images {
+        if self.no_images.unwrap_or(false) {
             arguments.push("--no-images".to_string());
         }
-        if self.no_pdf_compression {
+        if self.no_pdf_compression.unwrap_or(false) {
             arguments.push("--no-pdf-compression".to_string());
         }
-        arguments
-            .extend_from_slice(&["--orientation".to_string(), self.orientation.clone().into()]);
-        arguments.extend_from_slice(&["--page-size".to_string(), self.page_size.clone().into()]);
-        arguments.extend_from_slice(&["--zoom".to_string(), format!("{:.2}", self.zoom)]);
+        let orientation = self.orientation.clone().unwrap_or(Orientation::Portrait);
+        arguments.extend_from_slice(&["--orientation".to_string(), orientation.into()]);
+        let page_size = self.page_size.clone().unwrap_or(PageSize::Letter);
+        arguments.extend_from_slice(&["--page-size".to_string(), page_size.into()]);
+        let zoom = self.zoom.unwrap_or_else(default_zoom);
+        arguments.extend_from_slice(&["--zoom".to_string(), format!("{:.2}", zoom)]);
         arguments
     }
-    pub(crate) fn set(&mut self, setting: Setting) {
-        use self::Setting::*;
-        match setting {
-            DisableExternalLinks(v) => self.disable_external_links = v,
-            DisableJavascript(v) => self.disable_javascript = v,
-            EnableForms(v) => self.enable_forms = v,
-            Dpi(v) => self.dpi = v,
-            Grayscale(v) => self.grayscale = v,
-            ImageDpi(v) => self.image_dpi = v,
-            ImageQuality(v) => self.image_quality = v,
-            LowQuality(v) => self.low_quality = v,
-            JavascriptDelay(v) => self.javascript_delay = v,
-            MarginBottom(v) => self.margin_bottom = v,
-            MarginLeft(v) => self.margin_left = v,
-            MarginRight(v) => self.margin_right = v,
-            MarginTop(v) => self.margin_top = v,
-            NoBackground(v) => self.no_background = v,
-            NoImages(v) => self.no_images = v,
-            NoPdfCompression(v) => self.no_pdf_compression = v,
-            Orientation(v) => self.orientation = v,
-            PageSize(v) => self.page_size = v,
-            Zoom(v) => self.zoom = v,
-        };
+
+    /// Returns the arguments of wkhtmltoimage, which only supports the
+    /// settings that don't concern pages.
+    pub(crate) fn to_image_arguments(&self) -> Vec<String> {
+        let mut arguments = Vec::new();
+        if self.disable_javascript.unwrap_or(false) {
+            arguments.push("--disable-javascript".to_string());
+        }
+        let javascript_delay = self
+            .javascript_delay
+            .unwrap_or_else(|| Duration::from_millis(200));
+        arguments.extend_from_slice(&[
+            "--javascript-delay".to_string(),
+            duration_to_millis(javascript_delay).to_string(),
+        ]);
+        arguments.extend_from_slice(&[
+            "--quality".to_string(),
+            self.image_quality.unwrap_or(94).to_string(),
+        ]);
+        if self.no_images.unwrap_or(false) {
+            arguments.push("--no-images".to_string());
+        }
+        if let Some(zoom) = self.zoom {
+            arguments.extend_from_slice(&["--zoom".to_string(), format!("{:.2}", zoom)]);
+        }
+        arguments
     }
 }
 
@@ -305,27 +366,26 @@ mod tests {
 
     #[test]
     fn test_to_arguments() {
-        let settings = Settings {
-            disable_external_links: true,
-            disable_javascript: true,
-            enable_forms: true,
-            dpi: 100,
-            grayscale: true,
-            image_dpi: 101,
-            image_quality: 102,
-            low_quality: true,
-            javascript_delay: Duration::from_millis(2000),
-            margin_bottom: "0.1in".to_string(),
-            margin_left: "0.2in".to_string(),
-            margin_right: "0.3in".to_string(),
-            margin_top: "0.4in".to_string(),
-            no_background: true,
-            no_images: true,
-            no_pdf_compression: true,
-            orientation: Orientation::Landscape,
-            page_size: PageSize::A4,
-            zoom: 2.0,
-        };
+        let settings = Settings::new()
+            .set(Setting::DisableExternalLinks(true))
+            .set(Setting::DisableJavascript(true))
+            .set(Setting::EnableForms(true))
+            .set(Setting::Dpi(100))
+            .set(Setting::Grayscale(true))
+            .set(Setting::ImageDpi(101))
+            .set(Setting::ImageQuality(102))
+            .set(Setting::LowQuality(true))
+            .set(Setting::JavascriptDelay(Duration::from_millis(2000)))
+            .set(Setting::MarginBottom("0.1in".to_string()))
+            .set(Setting::MarginLeft("0.2in".to_string()))
+            .set(Setting::MarginRight("0.3in".to_string()))
+            .set(Setting::MarginTop("0.4in".to_string()))
+            .set(Setting::NoBackground(true))
+            .set(Setting::NoImages(true))
+            .set(Setting::NoPdfCompression(true))
+            .set(Setting::Orientation(Orientation::Landscape))
+            .set(Setting::PageSize(PageSize::A4))
+            .set(Setting::Zoom(2.0));
         let arguments = settings.to_arguments();
         let mut s = String::new();
         for argument in arguments {
@@ -339,4 +399,21 @@ mod tests {
                        --no-pdf-compression --orientation Landscape --page-size A4 --zoom 2.00";
         assert_eq!(desired, output);
     }
+
+    #[test]
+    fn test_or() {
+        let defaults = Settings::new()
+            .set(Setting::Orientation(Orientation::Portrait))
+            .set(Setting::Zoom(2.0));
+        let settings = Settings::new()
+            .set(Setting::Orientation(Orientation::Landscape))
+            .set(Setting::Dpi(300))
+            .or(&defaults);
+        let arguments = settings.to_arguments().join(" ");
+        assert!(arguments.contains("--orientation Landscape"));
+        assert!(arguments.contains("--dpi 300"));
+        assert!(arguments.contains("--zoom 2.00"));
+        assert!(arguments.contains("--page-size Letter"));
+        assert_eq!(defaults, Settings::new().or(&defaults));
+    }
 }
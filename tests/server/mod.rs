@@ -0,0 +1,191 @@
+//! A minimal HTTP/1.1 server on localhost, so that the tests of the clients
+//! don't depend on the network.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use urls2disk::Url;
+
+/// A request the server received
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Returns the value of the header `name`, if the request has one.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// The response the server sends to a request
+#[derive(Clone, Debug)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    truncate: Option<usize>,
+}
+
+impl Response {
+    pub fn new(status: u16, body: &[u8]) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: body.to_vec(),
+            truncate: None,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Closes the connection after the first `len` bytes of the body, even
+    /// though the `Content-Length` is that of the whole body.
+    pub fn truncate(mut self, len: usize) -> Response {
+        self.truncate = Some(len);
+        self
+    }
+}
+
+/// A `Server` answers every request with the `Response` its handler returns
+/// for it, and records the requests it received. It stops once dropped.
+pub struct Server {
+    port: u16,
+    requests: Arc<Mutex<Vec<Request>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Server {
+    pub fn new<F>(handler: F) -> Server
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let handler = Arc::new(handler);
+        {
+            let requests = requests.clone();
+            let stopped = stopped.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => continue,
+                    };
+                    let requests = requests.clone();
+                    let handler = handler.clone();
+                    thread::spawn(move || serve(stream, &*handler, &requests));
+                }
+            });
+        }
+        Server {
+            port,
+            requests,
+            stopped,
+        }
+    }
+
+    /// Returns the URL of `path` on this server.
+    pub fn url(&self, path: &str) -> Url {
+        Url::parse(&format!("http://127.0.0.1:{}{}", self.port, path)).unwrap()
+    }
+
+    /// Returns the requests received so far, in order.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake the listener so that it sees it was stopped.
+        let _ = TcpStream::connect(("127.0.0.1", self.port));
+    }
+}
+
+/// Answers the single request of `stream` (every response closes the
+/// connection).
+fn serve<F>(stream: TcpStream, handler: &F, requests: &Mutex<Vec<Request>>)
+where
+    F: Fn(&Request) -> Response,
+{
+    let request = match read_request(&stream) {
+        Some(request) => request,
+        None => return,
+    };
+    requests.lock().unwrap().push(request.clone());
+    let response = handler(&request);
+    let mut head = format!("HTTP/1.1 {} Test\r\n", response.status);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    ));
+    let body = match response.truncate {
+        Some(len) => &response.body[..len],
+        None => &response.body[..],
+    };
+    let mut stream = stream;
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(body);
+    let _ = stream.flush();
+}
+
+fn read_request(stream: &TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let colon = line.find(':')?;
+        headers.push((
+            line[..colon].trim().to_string(),
+            line[colon + 1..].trim().to_string(),
+        ));
+    }
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+    };
+    let len = request
+        .header("Content-Length")
+        .and_then(|len| len.parse().ok())
+        .unwrap_or(0);
+    request.body = vec![0; len];
+    reader.read_exact(&mut request.body).ok()?;
+    Some(request)
+}
@@ -1,11 +1,24 @@
+extern crate futures;
+extern crate tokio_core;
 extern crate url;
 extern crate urls2disk;
 
+mod server;
+
 use std::fs;
-use std::path::Path;
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use urls2disk::{Client, ClientBuilder, Result, SimpleDocument, Url};
+use futures::Stream;
+use server::{Response, Server};
+use tokio_core::reactor::Core;
+use urls2disk::{
+    Client, ClientBuilder, ClientObserver, Digest, DigestMismatch, Document, DocumentReport,
+    Headers, Method, Outcome, Output, OutputFormat, RefreshPolicy, Result, SimpleDocument,
+    StatusCode, TimeoutError, TimeoutKind, Timeouts, Url,
+};
 
 const NO_OF_URLS: usize = 100;
 
@@ -27,46 +40,709 @@ fn setup<P: AsRef<Path>>(data_directory: P) -> Result<Client> {
 #[ignore]
 fn test_client_html() {
     let data_directory = Path::new("./tests/test_output/test_client_html");
-    let client = setup(&data_directory).unwrap();
+    let client = setup(data_directory).unwrap();
     let mut documents = (0..NO_OF_URLS)
         .map(|i| {
             let document = SimpleDocument::new(
                 data_directory.join(format!("test{}.html", i)),
-                Url::parse("https://www.sec.gov/Archives/edgar/data/320193/000032019318000007/a10-qq1201812302017.htm").unwrap(),
+                // Distinct URLs, so that the documents don't share a request.
+                Url::parse(&format!("https://www.sec.gov/Archives/edgar/data/320193/000032019318000007/a10-qq1201812302017.htm?i={}", i)).unwrap(),
                 false,
             );
             Box::new(document)
         })
         .collect::<Vec<Box<SimpleDocument>>>();
     let start = Instant::now();
-    client.get_documents(&mut documents).unwrap();
+    let report = client.get_documents(&mut documents).unwrap();
+    assert!(report.is_ok());
     let elapsed = elapsed(&start);
     let requests_per_second = documents.len() as f32 / elapsed;
     println!("requests per second: {}", requests_per_second);
-    assert!(requests_per_second < 10 as f32);
+    assert!(requests_per_second < 10f32);
 }
 
 #[test]
 #[ignore]
 fn test_client_wkhtmltopdf() {
     let data_directory = Path::new("./tests/test_output/test_client_wkhtmltopdf");
-    let client = setup(&data_directory).unwrap();
+    let client = setup(data_directory).unwrap();
     let mut documents = (0..NO_OF_URLS)
         .map(|i| {
             let wkhtmltopdf = i % 10 == 0;
             let document = SimpleDocument::new(
                 data_directory.join(
                     format!("test{}{}", i, if wkhtmltopdf {".pdf"} else {".html"})),
-                Url::parse("https://www.sec.gov/Archives/edgar/data/320193/000032019318000007/a10-qq1201812302017.htm").unwrap(),
+                Url::parse(&format!("https://www.sec.gov/Archives/edgar/data/320193/000032019318000007/a10-qq1201812302017.htm?i={}", i)).unwrap(),
                 wkhtmltopdf,
             );
             Box::new(document)
         })
         .collect::<Vec<Box<SimpleDocument>>>();
     let start = Instant::now();
-    client.get_documents(&mut documents).unwrap();
+    let report = client.get_documents(&mut documents).unwrap();
+    assert!(report.is_ok());
     let elapsed = elapsed(&start);
     let requests_per_second = documents.len() as f32 / elapsed;
     println!("requests per second: {}", requests_per_second);
-    assert!(requests_per_second < 10 as f32);
+    assert!(requests_per_second < 10f32);
+}
+
+#[test]
+#[ignore]
+fn test_async_client_html() {
+    let data_directory = Path::new("./tests/test_output/test_async_client_html");
+    setup(data_directory).unwrap();
+    let mut core = Core::new().unwrap();
+    let client = ClientBuilder::default()
+        .build_async(&core.handle())
+        .unwrap();
+    let documents = (0..NO_OF_URLS)
+        .map(|i| {
+            SimpleDocument::new(
+                data_directory.join(format!("test{}.html", i)),
+                Url::parse("https://www.sec.gov/Archives/edgar/data/320193/000032019318000007/a10-qq1201812302017.htm").unwrap(),
+                false,
+            )
+        })
+        .collect::<Vec<SimpleDocument>>();
+    let start = Instant::now();
+    let stream = client
+        .get_documents_iter(documents)
+        .for_each(|(document, report)| {
+            assert!(report.is_ok());
+            assert!(document.bytes().is_some());
+            Ok(())
+        });
+    core.run(stream).unwrap();
+    let elapsed = elapsed(&start);
+    let requests_per_second = NO_OF_URLS as f32 / elapsed;
+    println!("requests per second: {}", requests_per_second);
+    assert!(requests_per_second < 10f32);
+}
+
+#[test]
+#[ignore]
+fn test_client_keep_bytes() {
+    let data_directory = Path::new("./tests/test_output/test_client_keep_bytes");
+    setup(data_directory).unwrap();
+    let client = ClientBuilder::default()
+        .set_keep_bytes(false)
+        .build()
+        .unwrap();
+    let mut documents = vec![Box::new(SimpleDocument::new(
+        data_directory.join("test.html"),
+        Url::parse("https://www.sec.gov/Archives/edgar/data/320193/000032019318000007/a10-qq1201812302017.htm").unwrap(),
+        false,
+    ))];
+    let report = client.get_documents(&mut documents).unwrap();
+    assert!(report.is_ok());
+    assert!(documents[0].bytes().is_none());
+    let bytes_written = report.documents()[0].bytes_written().unwrap();
+    assert_eq!(
+        bytes_written,
+        fs::metadata(data_directory.join("test.html"))
+            .unwrap()
+            .len()
+    );
+}
+
+#[derive(Default)]
+struct CountingObserver {
+    queued: AtomicUsize,
+    started: AtomicUsize,
+    completed: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+impl ClientObserver for CountingObserver {
+    fn on_queued(&self, _url: &Url, _path: &Path) {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_started(&self, _url: &Url, _path: &Path) {
+        self.started.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_completed(&self, _report: &DocumentReport) {
+        self.completed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_failed(&self, _report: &DocumentReport) {
+        self.failed.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+#[ignore]
+fn test_client_observer() {
+    let data_directory = Path::new("./tests/test_output/test_client_observer");
+    setup(data_directory).unwrap();
+    let observer = Arc::new(CountingObserver::default());
+    let client = ClientBuilder::default()
+        .set_observer(observer.clone())
+        .build()
+        .unwrap();
+    let mut documents = (0..3)
+        .map(|i| {
+            Box::new(SimpleDocument::new(
+                data_directory.join(format!("test{}.html", i)),
+                Url::parse("https://www.sec.gov/Archives/edgar/data/320193/000032019318000007/a10-qq1201812302017.htm").unwrap(),
+                false,
+            ))
+        })
+        .collect::<Vec<Box<SimpleDocument>>>();
+    client.get_documents(&mut documents).unwrap();
+    client.get_documents(&mut documents).unwrap();
+    assert_eq!(6, observer.queued.load(Ordering::SeqCst));
+    assert_eq!(3, observer.started.load(Ordering::SeqCst));
+    assert_eq!(6, observer.completed.load(Ordering::SeqCst));
+    assert_eq!(0, observer.failed.load(Ordering::SeqCst));
+}
+
+#[test]
+#[ignore]
+fn test_client_iter() {
+    let data_directory = Path::new("./tests/test_output/test_client_iter");
+    let client = setup(data_directory).unwrap();
+    let documents = (0..NO_OF_URLS).map(move |i| {
+        SimpleDocument::new(
+            data_directory.join(format!("test{}.html", i)),
+            Url::parse("https://www.sec.gov/Archives/edgar/data/320193/000032019318000007/a10-qq1201812302017.htm").unwrap(),
+            false,
+        )
+    });
+    let mut count = 0;
+    for (document, report) in client.get_documents_iter(documents) {
+        assert!(report.is_ok());
+        assert!(document.bytes().is_some());
+        count += 1;
+    }
+    assert_eq!(NO_OF_URLS, count);
+}
+
+/// A `Document` of a type other than `SimpleDocument`, configured through
+/// its `with_*` methods
+struct TestDocument {
+    path: PathBuf,
+    url: Url,
+    bytes: Option<Vec<u8>>,
+    output_format: OutputFormat,
+    priority: i32,
+    expected_digest: Option<Digest>,
+    method: Method,
+    headers: Headers,
+    body: Option<Vec<u8>>,
+}
+
+impl TestDocument {
+    fn new(path: PathBuf, url: Url) -> TestDocument {
+        TestDocument {
+            path,
+            url,
+            bytes: None,
+            output_format: OutputFormat::Raw,
+            priority: 0,
+            expected_digest: None,
+            method: Method::Get,
+            headers: Headers::new(),
+            body: None,
+        }
+    }
+
+    fn with_output_format(mut self, output_format: OutputFormat) -> TestDocument {
+        self.output_format = output_format;
+        self
+    }
+
+    fn with_priority(mut self, priority: i32) -> TestDocument {
+        self.priority = priority;
+        self
+    }
+
+    fn with_expected_digest(mut self, expected_digest: Digest) -> TestDocument {
+        self.expected_digest = Some(expected_digest);
+        self
+    }
+
+    fn with_header(mut self, name: &'static str, value: &str) -> TestDocument {
+        self.headers.set_raw(name, value.to_string());
+        self
+    }
+
+    /// Makes the document a `POST` of `form`.
+    fn with_form(mut self, form: &str) -> TestDocument {
+        self.method = Method::Post;
+        self.body = Some(form.as_bytes().to_vec());
+        self
+    }
+}
+
+impl Document for TestDocument {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+    fn url(&self) -> &Url {
+        &self.url
+    }
+    fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+    fn set_bytes(&mut self, bytes: Option<Vec<u8>>) {
+        self.bytes = bytes;
+    }
+    fn priority(&self) -> i32 {
+        self.priority
+    }
+    fn expected_digest(&self) -> Option<Digest> {
+        self.expected_digest.clone()
+    }
+    fn method(&self) -> Method {
+        self.method.clone()
+    }
+    fn headers(&self) -> Headers {
+        self.headers.clone()
+    }
+    fn body(&self) -> Option<Vec<u8>> {
+        self.body.clone()
+    }
+}
+
+#[test]
+fn test_client_mixed_documents() {
+    let data_directory = Path::new("./tests/test_output/test_client_mixed_documents");
+    let client = setup(data_directory).unwrap();
+    let server = Server::new(|_| Response::new(200, b"document"));
+    let url = server.url("/document.html");
+    let mut documents: Vec<Box<dyn Document + Send>> = vec![
+        Box::new(SimpleDocument::new(
+            data_directory.join("simple.html"),
+            url.clone(),
+            false,
+        )),
+        Box::new(TestDocument::new(
+            data_directory.join("test.html"),
+            url.clone(),
+        )),
+    ];
+    let report = client.get_documents(&mut documents).unwrap();
+    assert!(report.is_ok());
+    for name in &["simple.html", "test.html"] {
+        assert_eq!(
+            b"document".to_vec(),
+            fs::read(data_directory.join(name)).unwrap()
+        );
+    }
+
+    let documents: Vec<Box<dyn Document + Send>> = vec![
+        Box::new(SimpleDocument::new(
+            data_directory.join("simple_iter.html"),
+            url.clone(),
+            false,
+        )),
+        Box::new(TestDocument::new(
+            data_directory.join("test_iter.html"),
+            url,
+        )),
+    ];
+    let reports = client
+        .get_documents_iter(documents)
+        .map(|(_, report)| report)
+        .collect::<Vec<_>>();
+    assert_eq!(2, reports.len());
+    assert!(reports.iter().all(|report| report.is_ok()));
+}
+
+#[test]
+#[ignore]
+fn test_client_timeouts() {
+    let data_directory = Path::new("./tests/test_output/test_client_timeouts");
+    setup(data_directory).unwrap();
+    let client = ClientBuilder::default()
+        .set_timeouts(Timeouts::new().set_connect(Duration::from_millis(1)))
+        .build()
+        .unwrap();
+    let mut documents = vec![Box::new(SimpleDocument::new(
+        data_directory.join("test.html"),
+        Url::parse("https://www.sec.gov/Archives/edgar/data/320193/000032019318000007/a10-qq1201812302017.htm").unwrap(),
+        false,
+    ))];
+    let report = client.get_documents(&mut documents).unwrap();
+    let error = report.documents()[0].error().unwrap();
+    let error = error.downcast_ref::<TimeoutError>().unwrap();
+    assert_eq!(TimeoutKind::Connect, error.kind());
+}
+
+#[derive(Default)]
+struct StartedObserver {
+    started: Mutex<Vec<PathBuf>>,
+}
+
+impl ClientObserver for StartedObserver {
+    fn on_started(&self, _url: &Url, path: &Path) {
+        self.started.lock().unwrap().push(path.to_path_buf());
+    }
+}
+
+#[test]
+fn test_client_priority() {
+    let data_directory = Path::new("./tests/test_output/test_client_priority");
+    setup(data_directory).unwrap();
+    let server = Server::new(|_| Response::new(200, b"<html></html>"));
+    let observer = Arc::new(StartedObserver::default());
+    let client = ClientBuilder::default()
+        .set_max_threads_io(1)
+        .set_observer(observer.clone())
+        .build()
+        .unwrap();
+    let mut documents = [(0, true), (0, false), (1, false), (0, false), (1, true)]
+        .iter()
+        .enumerate()
+        .map(|(i, &(priority, wkhtmltopdf))| {
+            let extension = if wkhtmltopdf { "pdf" } else { "html" };
+            let path = data_directory.join(format!("{}.{}", i, extension));
+            // Distinct URLs, so that the documents don't share a request.
+            let url = server.url(&format!("/{}.html", i));
+            Box::new(
+                TestDocument::new(path, url)
+                    .with_output_format(OutputFormat::from(wkhtmltopdf))
+                    .with_priority(priority),
+            )
+        })
+        .collect::<Vec<_>>();
+    let report = client.get_documents(&mut documents).unwrap();
+    for (document, report) in documents.iter().zip(report.documents()) {
+        assert_eq!(document.path(), report.path());
+        // The PDFs depend on wkhtmltopdf being installed.
+        if document.output_format == OutputFormat::Raw {
+            assert!(report.is_ok());
+        }
+    }
+
+    // With a single io thread, raw documents start strictly by priority.
+    let started = observer.started.lock().unwrap();
+    let raw = started
+        .iter()
+        .filter(|path| path.extension().unwrap() == "html")
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            &data_directory.join("2.html"),
+            &data_directory.join("1.html"),
+            &data_directory.join("3.html"),
+        ],
+        raw
+    );
+}
+
+#[test]
+fn test_client_duplicates() {
+    let data_directory = Path::new("./tests/test_output/test_client_duplicates");
+    let client = setup(data_directory).unwrap();
+    let server = Server::new(|_| Response::new(200, b"document"));
+    let url = server.url("/document.html");
+    let mut documents = vec![
+        Box::new(SimpleDocument::new(
+            data_directory.join("0.html"),
+            url.clone(),
+            false,
+        )),
+        Box::new(SimpleDocument::new(
+            data_directory.join("1.html"),
+            url.clone(),
+            false,
+        )),
+    ];
+    let report = client.get_documents(&mut documents).unwrap();
+    assert!(report.is_ok());
+    assert_eq!(1, report.documents()[0].attempts());
+    assert_eq!(1, server.requests().len());
+    for name in &["0.html", "1.html"] {
+        assert_eq!(
+            b"document".to_vec(),
+            fs::read(data_directory.join(name)).unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_client_duplicates_different_digests() {
+    let data_directory = Path::new("./tests/test_output/test_client_duplicates_different_digests");
+    let client = setup(data_directory).unwrap();
+    let server = Server::new(|_| Response::new(200, b"document"));
+    let url = server.url("/document.html");
+    let mut documents = ["0", "1"]
+        .iter()
+        .map(|digit| {
+            let path = data_directory.join(format!("{}.html", digit));
+            Box::new(
+                TestDocument::new(path, url.clone())
+                    .with_expected_digest(Digest::sha256(&digit.repeat(64))),
+            )
+        })
+        .collect::<Vec<_>>();
+    // The documents don't share a fetch, since they expect different digests.
+    client.get_documents(&mut documents).unwrap();
+    assert_eq!(2, server.requests().len());
+}
+
+#[test]
+fn test_client_duplicates_rate_limit() {
+    let data_directory = Path::new("./tests/test_output/test_client_duplicates_rate_limit");
+    setup(data_directory).unwrap();
+    let client = ClientBuilder::default()
+        .set_max_requests_per_second(1)
+        .build()
+        .unwrap();
+    let server = Server::new(|_| Response::new(200, b"document"));
+    let url = server.url("/document.html");
+    let mut documents = (0..10)
+        .map(|i| {
+            let path = data_directory.join(format!("test{}.html", i));
+            Box::new(SimpleDocument::new(path, url.clone(), false))
+        })
+        .collect::<Vec<_>>();
+    let start = Instant::now();
+    let report = client.get_documents(&mut documents).unwrap();
+    assert!(report.is_ok());
+    // The documents share a single request, so the limit of one request per
+    // second doesn't space them out.
+    assert!(elapsed(&start) < 5f32);
+    assert_eq!(1, server.requests().len());
+    for report in report.documents() {
+        assert_eq!(1, report.attempts());
+        assert!(report.path().exists());
+    }
+}
+
+#[test]
+#[ignore]
+fn test_client_outputs() {
+    let data_directory = Path::new("./tests/test_output/test_client_outputs");
+    let client = setup(data_directory).unwrap();
+    let url = Url::parse(
+        "https://www.sec.gov/Archives/edgar/data/320193/000032019318000007/a10-qq1201812302017.htm",
+    )
+    .unwrap();
+    let mut documents = vec![Box::new(
+        SimpleDocument::new(data_directory.join("0.html"), url, false)
+            .add_output(Output::new(data_directory.join("0.pdf"), OutputFormat::Pdf))
+            .add_output(Output::new(data_directory.join("0.png"), OutputFormat::Png)),
+    )];
+    let report = client.get_documents(&mut documents).unwrap();
+    assert!(report.is_ok());
+    let report = &report.documents()[0];
+    assert_eq!(Outcome::Downloaded, report.outcome());
+    assert_eq!(
+        vec![Outcome::Converted, Outcome::Converted],
+        report
+            .outputs()
+            .iter()
+            .map(DocumentReport::outcome)
+            .collect::<Vec<_>>()
+    );
+    assert!(data_directory.join("0.pdf").exists());
+    assert!(data_directory.join("0.png").exists());
+    assert!(documents[0].bytes().is_some());
+    let response = documents[0].response().unwrap();
+    assert_eq!(Some(StatusCode::Ok), response.status());
+    assert!(response.content_type().unwrap().starts_with("text/html"));
+    assert!(response.conversion_duration().is_none());
+}
+
+#[test]
+fn test_client_post() {
+    let data_directory = Path::new("./tests/test_output/test_client_post");
+    let client = setup(data_directory).unwrap();
+    // Echoes the parts of the request the documents set.
+    let server = Server::new(|request| {
+        let echo = format!(
+            "{} {} {}",
+            request.method,
+            request.header("X-Urls2disk").unwrap_or_default(),
+            String::from_utf8_lossy(&request.body)
+        );
+        Response::new(200, echo.as_bytes())
+    });
+    let url = server.url("/post");
+    let mut documents = ["rust", "pdf"]
+        .iter()
+        .enumerate()
+        .map(|(i, q)| {
+            Box::new(
+                TestDocument::new(data_directory.join(format!("{}.txt", i)), url.clone())
+                    .with_header("X-Urls2disk", "test")
+                    .with_form(&format!("q={}", q)),
+            )
+        })
+        .collect::<Vec<_>>();
+    let report = client.get_documents(&mut documents).unwrap();
+    assert!(report.is_ok());
+    // The documents don't share a request, since their bodies differ.
+    assert_eq!(2, server.requests().len());
+    for &(name, echo) in &[("0.txt", "POST test q=rust"), ("1.txt", "POST test q=pdf")] {
+        assert_eq!(echo, fs::read_to_string(data_directory.join(name)).unwrap());
+    }
+}
+
+fn digest_mismatch(report: &DocumentReport) -> &DigestMismatch {
+    report
+        .error()
+        .and_then(|error| error.downcast_ref::<DigestMismatch>())
+        .unwrap()
+}
+
+#[test]
+fn test_client_duplicates_digests() {
+    let data_directory = Path::new("./tests/test_output/test_client_duplicates_digests");
+    let client = setup(data_directory).unwrap();
+    let server = Server::new(|_| Response::new(200, b"document"));
+    let url = server.url("/document.html");
+    let document = |name: &str, expected_digest: Option<&Digest>| {
+        let document = TestDocument::new(data_directory.join(name), url.clone());
+        Box::new(match expected_digest {
+            Some(expected_digest) => document.with_expected_digest(expected_digest.clone()),
+            None => document,
+        })
+    };
+
+    // Every document is verified against its own digest.
+    let wrong = Digest::sha256(&"0".repeat(64));
+    let mut documents = vec![
+        document("0.html", Some(&wrong)),
+        document("1.html", Some(&Digest::sha256(&"1".repeat(64)))),
+    ];
+    let report = client.get_documents(&mut documents).unwrap();
+    for (document, report) in documents.iter().zip(report.documents()) {
+        assert_eq!(
+            document.expected_digest.as_ref().unwrap(),
+            digest_mismatch(report).expected()
+        );
+        assert!(!document.path.exists());
+    }
+    let actual = digest_mismatch(&report.documents()[0]).actual().clone();
+
+    // A wrong digest doesn't fail the documents with the right one, and the
+    // digest of a document is checked even if the first document of its URL
+    // doesn't have one.
+    let mut documents = vec![
+        document("2.html", None),
+        document("3.html", Some(&wrong)),
+        document("4.html", Some(&actual)),
+    ];
+    let report = client.get_documents(&mut documents).unwrap();
+    let reports = report.documents();
+    assert!(reports[0].is_ok());
+    assert_eq!(&wrong, digest_mismatch(&reports[1]).expected());
+    assert!(reports[2].is_ok());
+    assert!(!data_directory.join("3.html").exists());
+    assert_eq!(
+        b"document".to_vec(),
+        fs::read(data_directory.join("4.html")).unwrap()
+    );
+}
+
+#[test]
+fn test_client_resume() {
+    let data_directory = Path::new("./tests/test_output/test_client_resume");
+    let client = setup(data_directory).unwrap();
+    let discard_requests = AtomicUsize::new(0);
+    let server = Server::new(move |request| {
+        let first = || {
+            Response::new(200, b"01234567")
+                .header("ETag", "\"v1\"")
+                .truncate(4)
+        };
+        match (request.path.as_str(), request.header("Range")) {
+            // The first attempt is cut off after 4 bytes.
+            ("/resume", None) | ("/restart", None) => first(),
+            ("/resume", Some(_)) => Response::new(206, b"4567")
+                .header("ETag", "\"v1\"")
+                .header("Content-Range", "bytes 4-7/8"),
+            // The document changed, so `If-Range` doesn't match.
+            ("/restart", Some(_)) => Response::new(200, b"abcdefgh").header("ETag", "\"v2\""),
+            ("/discard", None) => match discard_requests.fetch_add(1, Ordering::SeqCst) {
+                0 => first(),
+                _ => Response::new(200, b"01234567"),
+            },
+            ("/discard", Some(_)) => Response::new(416, b""),
+            _ => Response::new(404, b""),
+        }
+    });
+    let names = ["resume", "restart", "discard"];
+    let mut documents = names
+        .iter()
+        .map(|name| {
+            Box::new(SimpleDocument::new(
+                data_directory.join(name),
+                server.url(&format!("/{}", name)),
+                false,
+            ))
+        })
+        .collect::<Vec<_>>();
+    let part = |name: &str| data_directory.join(format!("{}.part", name));
+
+    let report = client.get_documents(&mut documents).unwrap();
+    for (name, report) in names.iter().zip(report.documents()) {
+        assert!(!report.is_ok());
+        assert!(!data_directory.join(name).exists());
+        assert_eq!(b"0123".to_vec(), fs::read(part(name)).unwrap());
+    }
+
+    let report = client.get_documents(&mut documents).unwrap();
+    assert!(report.is_ok());
+    for &(name, bytes) in &[
+        ("resume", &b"01234567"[..]),
+        ("restart", &b"abcdefgh"[..]),
+        ("discard", &b"01234567"[..]),
+    ] {
+        assert_eq!(bytes.to_vec(), fs::read(data_directory.join(name)).unwrap());
+        assert!(!part(name).exists());
+        assert!(!data_directory
+            .join(format!("{}.part.validators", name))
+            .exists());
+    }
+    let resumed = server
+        .requests()
+        .into_iter()
+        .filter(|request| request.path == "/resume")
+        .collect::<Vec<_>>();
+    assert_eq!(2, resumed.len());
+    assert_eq!(Some("bytes=4-"), resumed[1].header("Range"));
+    assert_eq!(Some("\"v1\""), resumed[1].header("If-Range"));
+}
+
+#[test]
+fn test_client_not_modified() {
+    let data_directory = Path::new("./tests/test_output/test_client_not_modified");
+    setup(data_directory).unwrap();
+    let client = ClientBuilder::default()
+        .set_refresh_policy(RefreshPolicy::Revalidate)
+        .build()
+        .unwrap();
+    let last_modified = "Wed, 21 Oct 2015 07:28:00 GMT";
+    let server = Server::new(move |request| match request.header("If-None-Match") {
+        Some("\"v1\"") => Response::new(304, b"").header("ETag", "\"v1\""),
+        // A body that differs from the one on disk, which must not be written.
+        Some(_) => Response::new(200, b"changed"),
+        None => Response::new(200, b"original")
+            .header("ETag", "\"v1\"")
+            .header("Last-Modified", last_modified),
+    });
+    let path = data_directory.join("test.html");
+    let mut documents = vec![Box::new(SimpleDocument::new(
+        path.clone(),
+        server.url("/test.html"),
+        false,
+    ))];
+    let report = client.get_documents(&mut documents).unwrap();
+    assert_eq!(Outcome::Downloaded, report.documents()[0].outcome());
+
+    let report = client.get_documents(&mut documents).unwrap();
+    assert!(report.is_ok());
+    assert_eq!(Outcome::NotModified, report.documents()[0].outcome());
+    assert_eq!(b"original".to_vec(), fs::read(&path).unwrap());
+    let requests = server.requests();
+    assert_eq!(2, requests.len());
+    assert_eq!(Some("\"v1\""), requests[1].header("If-None-Match"));
+    assert_eq!(Some(last_modified), requests[1].header("If-Modified-Since"));
 }